@@ -0,0 +1,82 @@
+#[macro_use]
+extern crate criterion;
+extern crate wealth_pulse;
+
+use criterion::{black_box, Criterion};
+use wealth_pulse::{parse_journal, parse_price_db, parse_quantity};
+
+
+const SYMBOLS: [&str; 4] = ["AAPL", "\"MUTF2351\"", "GOOG", "VTI"];
+
+/// A price DB with one price per symbol per day for about three years.
+fn price_db_fixture() -> String {
+	let mut text = String::new();
+	for day in 0..1000 {
+		for (i, symbol) in SYMBOLS.iter().enumerate() {
+			text.push_str(&format!(
+				"P {:04}-{:02}-{:02} {} ${}.{:02}\n",
+				2013 + day / 336, day / 28 % 12 + 1, day % 28 + 1,
+				symbol, 100 + i * 50 + day % 75, day % 100));
+		}
+	}
+	text
+}
+
+/// A journal of transactions with a handful of postings each, interleaved
+/// with prices.
+fn journal_fixture() -> String {
+	let mut text = String::new();
+	for day in 0..2000 {
+		let date = format!("{:04}-{:02}-{:02}", 2010 + day / 336, day / 28 % 12 + 1, day % 28 + 1);
+		text.push_str(&format!("{} * (#{}) Grocery Store ;weekly shop\n", date, day));
+		text.push_str(&format!("    Expenses:Food:Groceries  ${}.{:02}\n", 20 + day % 80, day % 100));
+		text.push_str("    Expenses:Household  $12.50 ; :kitchen:\n");
+		text.push_str("    Assets:Checking\n\n");
+		text.push_str(&format!("{} ! Broker\n", date));
+		text.push_str("    Assets:Investments  10 AAPL @ $313.38\n");
+		text.push_str("    Assets:Cash  $-3133.80\n\n");
+		text.push_str(&format!("P {} AAPL $313.38\n\n", date));
+	}
+	text
+}
+
+/// A journal whose payees and comments are each several thousand characters,
+/// to show the cost of collecting long runs of characters.
+fn long_text_fixture() -> String {
+	let payee = "WonderMart - groceries, kitchen supplies (pot), light bulbs ".repeat(100);
+	let comment = " receipt scanned, filed under household".repeat(100);
+	let mut text = String::new();
+	for day in 0..100 {
+		text.push_str(&format!("2015-10-{:02} * {};{}\n", day % 28 + 1, payee, comment));
+		text.push_str("    Expenses:Food  $45.00\n");
+		text.push_str("    Assets:Cash\n\n");
+	}
+	text
+}
+
+fn bench_price_db(c: &mut Criterion) {
+	let text = price_db_fixture();
+	c.bench_function("price_db", |b| b.iter(|| parse_price_db(black_box(&text)).unwrap()));
+}
+
+fn bench_journal(c: &mut Criterion) {
+	let text = journal_fixture();
+	c.bench_function("journal", |b| b.iter(|| parse_journal(black_box(&text)).unwrap()));
+}
+
+fn bench_long_text(c: &mut Criterion) {
+	let text = long_text_fixture();
+	c.bench_function("long_text", |b| b.iter(|| parse_journal(black_box(&text)).unwrap()));
+}
+
+fn bench_quantity(c: &mut Criterion) {
+	let quantities = ["5", "-313.38", "1,234,567.89", "0.00000001"];
+	c.bench_function("quantity", |b| b.iter(|| {
+		for quantity in quantities.iter() {
+			parse_quantity(black_box(quantity)).unwrap();
+		}
+	}));
+}
+
+criterion_group!(benches, bench_price_db, bench_journal, bench_long_text, bench_quantity);
+criterion_main!(benches);
@@ -0,0 +1,8307 @@
+#![allow(clippy::redundant_field_names, clippy::enum_variant_names,
+	clippy::type_complexity)]
+
+extern crate combine;
+extern crate rust_decimal;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+use combine::{alpha_num, any, between, char, crlf, digit, many, many1, newline,
+	not_followed_by, optional, parser, satisfy, sep_by1, sep_end_by, skip_many,
+	skip_many1, string, try, Parser, ParserExt, ParseResult, ParseError};
+use combine::combinator::FnParser;
+use combine::primitives::{Consumed, State, Stream};
+use rust_decimal::{Decimal, RoundingStrategy};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::io;
+use std::io::{BufRead, Read};
+use std::iter;
+use std::iter::FromIterator;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AmountFormat {
+	SymbolLeftNoSpace,
+	SymbolLeftWithSpace,
+	SymbolRightNoSpace,
+	SymbolRightWithSpace,
+	/// Symbol on the left, with a negative sign written before it. e.g. -$5.00
+	MinusSymbolLeftNoSpace,
+	/// e.g. -$ 5.00
+	MinusSymbolLeftWithSpace,
+	/// Symbol on the right, with a negative sign written after the quantity,
+	/// as in accounting. e.g. 5.00-USD
+	TrailingMinusSymbolRightNoSpace,
+	/// e.g. 5.00- USD
+	TrailingMinusSymbolRightWithSpace
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TransactionStatus {
+	Cleared,
+	Uncleared,
+	Unmarked
+}
+
+/// A calendar date. Dates order chronologically, by year, then month, then
+/// day. The separator is only kept for display, so 2015/10/17 and 2015-10-17
+/// are the same date.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Date {
+	pub year: i32,
+	pub month: i32,
+	pub day: i32,
+	#[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "DateSeparator::is_hyphen"))]
+	pub separator: DateSeparator
+}
+
+/// The character a date was written with between its year, month and day.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DateSeparator {
+	/// e.g. 2015-10-17
+	#[default]
+	Hyphen,
+	/// e.g. 2015/10/17
+	Slash
+}
+
+impl DateSeparator {
+	#[cfg(feature = "serde")]
+	fn is_hyphen(&self) -> bool {
+		*self == DateSeparator::Hyphen
+	}
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Header {
+	pub line_number: i32,
+	pub date: Date,
+	/// The date the transaction takes effect, when written as `date=effective`.
+	pub effective_date: Option<Date>,
+	pub status: TransactionStatus,
+	pub code: Option<String>,
+	pub payee: String,
+	pub comment: Option<String>
+}
+
+/// A commodity symbol. Symbols are equal when their values are, whether or not
+/// either was quoted, so "AAPL" and AAPL are the same commodity.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Symbol {
+	pub value: String,
+	pub quoted: bool
+}
+
+impl PartialEq for Symbol {
+	fn eq(&self, other: &Symbol) -> bool {
+		self.value == other.value
+	}
+}
+
+impl Eq for Symbol {}
+
+impl PartialOrd for Symbol {
+	fn partial_cmp(&self, other: &Symbol) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for Symbol {
+	fn cmp(&self, other: &Symbol) -> Ordering {
+		self.value.cmp(&other.value)
+	}
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Amount {
+	pub value: Decimal,
+	pub symbol: Symbol,
+	pub format: AmountFormat
+}
+
+/// The character separating the whole and fractional parts of a quantity.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DecimalMark {
+	/// e.g. 1,234.56
+	Point,
+	/// e.g. 1.234,56
+	Comma
+}
+
+/// Most digits a quantity may have unless configured otherwise. Well beyond
+/// the 28 or so digits a `Decimal` holds, so it only stops pathological input.
+pub const DEFAULT_MAX_DIGITS: usize = 64;
+
+/// How digits are grouped and the fractional part separated in a quantity.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct NumberFormat {
+	pub decimal_mark: DecimalMark,
+	/// Whether fractional digits are grouped too, in threes from the decimal
+	/// mark. e.g. 1,234.567,8
+	pub fractional_grouping: bool,
+	/// Most digits a quantity may have before parsing fails, to bound the
+	/// work done on untrusted input.
+	pub max_digits: usize
+}
+
+impl NumberFormat {
+	/// e.g. 1,234.56
+	pub const DECIMAL_POINT: NumberFormat = NumberFormat {
+		decimal_mark: DecimalMark::Point,
+		fractional_grouping: false,
+		max_digits: DEFAULT_MAX_DIGITS
+	};
+
+	/// e.g. 1.234,56
+	pub const DECIMAL_COMMA: NumberFormat = NumberFormat {
+		decimal_mark: DecimalMark::Comma,
+		fractional_grouping: false,
+		max_digits: DEFAULT_MAX_DIGITS
+	};
+
+	/// The characters grouping digits and marking the decimal, in that order.
+	fn separators(&self) -> (char, char) {
+		match self.decimal_mark {
+			DecimalMark::Point => (',', '.'),
+			DecimalMark::Comma => ('.', ',')
+		}
+	}
+}
+
+/// A sum of amounts in more than one commodity, with one amount per commodity.
+#[derive(PartialEq, Debug, Clone)]
+pub struct MixedAmount {
+	pub amounts: Vec<Amount>
+}
+
+/// An amount held as a whole number of its commodity's minor unit, e.g. cents
+/// for a commodity with a scale of 2.
+#[derive(PartialEq, Debug, Clone)]
+pub struct MinorUnitAmount {
+	pub units: i64,
+	pub scale: u32,
+	pub symbol: Symbol,
+	pub format: AmountFormat
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum AmountSource {
+	Provided,
+	Inferred
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum PostingType {
+	Real,
+	Virtual,
+	BalancedVirtual
+}
+
+/// A posting as parsed. Postings are equal when everything but their `gap`
+/// is, so a posting re-parsed from differently aligned text still matches.
+#[derive(Debug, Clone)]
+pub struct ParsedPosting {
+	pub line_number: i32,
+	/// The posting's own status, when marked separately from its
+	/// transaction's. e.g. * Assets:Cash
+	pub status: Option<TransactionStatus>,
+	pub full_account: String,
+	pub sub_accounts: Vec<String>,
+	pub posting_type: PostingType,
+	/// How many whitespace characters separated the account from what
+	/// follows it, so a formatter can reproduce the original alignment.
+	pub gap: usize,
+	pub amount: Option<Amount>,
+	pub amount_source: AmountSource,
+	pub lot_price: Option<Amount>,
+	pub cost: Option<Amount>,
+	pub balance_assertion: Option<Amount>,
+	pub comment: Option<String>,
+	/// A date for this posting alone, overriding the transaction's date. e.g.
+	/// ; [=2015-11-01]
+	pub date: Option<Date>
+}
+
+impl PartialEq for ParsedPosting {
+	fn eq(&self, other: &ParsedPosting) -> bool {
+		self.line_number == other.line_number
+			&& self.status == other.status
+			&& self.full_account == other.full_account
+			&& self.sub_accounts == other.sub_accounts
+			&& self.posting_type == other.posting_type
+			&& self.amount == other.amount
+			&& self.amount_source == other.amount_source
+			&& self.lot_price == other.lot_price
+			&& self.cost == other.cost
+			&& self.balance_assertion == other.balance_assertion
+			&& self.comment == other.comment
+			&& self.date == other.date
+	}
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Transaction {
+	pub header: Header,
+	pub postings: Vec<ParsedPosting>,
+	/// Tags from the `apply tag` blocks the transaction is inside, outermost
+	/// first.
+	pub applied_tags: Vec<String>
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct AccountDirective {
+	pub full_account: String,
+	pub sub_accounts: Vec<String>,
+	pub note: Option<String>,
+	pub aliases: Vec<String>
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct CommodityDirective {
+	pub symbol: Symbol,
+	pub format: Option<String>
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Price {
+	pub date: Date,
+	pub symbol: Symbol,
+	pub amount: Amount,
+	/// The whitespace written between the parts of the directive, when
+	/// parsed with spacing preserved.
+	#[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+	pub spacing: Option<PriceSpacing>
+}
+
+/// The whitespace after each of the first three parts of a price directive:
+/// the P, the date and the symbol.
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PriceSpacing {
+	pub after_marker: String,
+	pub after_date: String,
+	pub after_symbol: String
+}
+
+/// A collection of prices, as parsed from a price DB file.
+#[derive(PartialEq, Debug, Clone)]
+pub struct PriceDb {
+	pub prices: Vec<Price>
+}
+
+/// A parsed journal: its transactions and the prices found alongside them.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Ledger {
+	pub transactions: Vec<Transaction>,
+	pub prices: PriceDb,
+	/// From the last D directive, if any.
+	pub default_commodity: Option<Amount>,
+	pub commodities: Vec<CommodityDirective>,
+	/// From account directives, in the order they were declared.
+	pub declared_accounts: Vec<AccountDirective>
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Entry {
+	Transaction(Transaction),
+	Price(Price),
+	Include(String),
+	/// The default commodity and how to display it, from a D directive.
+	DefaultCommodity(Amount),
+	Commodity(CommodityDirective),
+	Account(AccountDirective),
+	/// The year for following dates written without one, from a Y directive.
+	Year(i32),
+	/// A top-level comment line, without its leading marker.
+	Comment(String),
+	/// The start of an `apply tag` block, with the tag applied.
+	ApplyTag(String),
+	/// The end of the innermost `apply tag` block.
+	EndApplyTag,
+	/// A budget that recurs every period, from a ~ line.
+	Periodic(PeriodicTransaction)
+}
+
+/// Postings that recur every period, written after a ~ and a period
+/// expression. Used as a budget.
+#[derive(PartialEq, Debug, Clone)]
+pub struct PeriodicTransaction {
+	pub period: Period,
+	pub postings: Vec<ParsedPosting>
+}
+
+/// The lines of the source an entry was parsed from, inclusive.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Span {
+	pub start_line: i32,
+	pub end_line: i32
+}
+
+/// How often a periodic transaction recurs. Each variant holds the number of
+/// units between occurrences, e.g. every 2 weeks is `Weekly(2)`.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Period {
+	Daily(u32),
+	Weekly(u32),
+	Monthly(u32),
+	Yearly(u32),
+	/// A period expression that isn't understood, kept as written.
+	Other(String)
+}
+
+/// Metadata carried in a comment.
+#[derive(PartialEq, Debug, Default)]
+pub struct CommentMeta {
+	/// Flag-style tags. e.g. :vacation:reimbursable:
+	pub tags: Vec<String>,
+	/// Typed key/value tags. e.g. trip: Paris 2015
+	pub values: Vec<(String, String)>
+}
+
+/// Which transaction statuses a report includes, mirroring ledger's
+/// --cleared, --pending (uncleared) and --unmarked flags.
+#[derive(PartialEq, Debug)]
+pub struct StatusFilter {
+	pub cleared: bool,
+	pub uncleared: bool,
+	pub unmarked: bool
+}
+
+impl Default for StatusFilter {
+	fn default() -> StatusFilter {
+		StatusFilter {
+			cleared: true,
+			uncleared: true,
+			unmarked: true
+		}
+	}
+}
+
+/// Options controlling how ledger text is parsed.
+#[derive(PartialEq, Debug)]
+pub struct ParserOptions {
+	pub account_separator: char,
+	/// Most entries a journal or price DB may contain before parsing fails,
+	/// to bound the work done on untrusted input. None for no limit.
+	pub max_entries: Option<usize>,
+	/// Whether transaction dates may be written with slashes, e.g. 2015/10/17.
+	pub allow_slash_dates: bool,
+	/// Whether posting amounts are written with a decimal comma, e.g. 1.234,56.
+	pub decimal_comma: bool,
+	/// The year for dates written without one, until a Y directive says
+	/// otherwise.
+	pub default_year: Option<i32>,
+	/// Whether transaction dates must be real calendar dates, so 2015-02-30
+	/// is an error.
+	pub strict_dates: bool,
+	/// Whether trailing whitespace is trimmed from payees, e.g. the space
+	/// before a comment in `Payee ;comment`.
+	pub trim_payees: bool,
+	/// Whether indented comment lines right after a posting's comment continue
+	/// it, so a long metadata value can wrap onto following lines.
+	pub metadata_continuation: bool,
+	/// Whether prices keep the whitespace between their parts, so they're
+	/// written back out aligned as they were.
+	pub preserve_spacing: bool,
+	/// Whether the fractional digits of posting amounts may be grouped, e.g.
+	/// 1,234.567,8.
+	pub fractional_grouping: bool,
+	/// Most digits a quantity in a posting amount may have.
+	pub max_quantity_digits: usize
+}
+
+impl Default for ParserOptions {
+	fn default() -> ParserOptions {
+		ParserOptions {
+			account_separator: ':',
+			max_entries: None,
+			allow_slash_dates: false,
+			decimal_comma: false,
+			default_year: None,
+			strict_dates: false,
+			trim_payees: false,
+			metadata_continuation: false,
+			preserve_spacing: false,
+			fractional_grouping: false,
+			max_quantity_digits: DEFAULT_MAX_DIGITS
+		}
+	}
+}
+
+impl ParserOptions {
+	/// How quantities in posting amounts are written.
+	fn number_format(&self) -> NumberFormat {
+		let decimal_mark = if self.decimal_comma {
+			DecimalMark::Comma
+		} else {
+			DecimalMark::Point
+		};
+		NumberFormat {
+			decimal_mark: decimal_mark,
+			fractional_grouping: self.fractional_grouping,
+			max_digits: self.max_quantity_digits
+		}
+	}
+
+	/// Counts one more parsed entry, failing once there are more than
+	/// `max_entries`.
+	fn count_entry(&self, count: &Cell<usize>) -> Result<(), WealthError> {
+		count.set(count.get() + 1);
+		match self.max_entries {
+			Some(max) if count.get() > max => Err(WealthError::TooManyEntries(max)),
+			_ => Ok(())
+		}
+	}
+}
+
+/// Kind of a lexical token, as used for syntax highlighting.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum TokenKind {
+	Date,
+	Account,
+	Amount,
+	Comment,
+	Symbol,
+	Keyword
+}
+
+/// A lexical token and the byte range it covers in the source.
+#[derive(PartialEq, Debug)]
+pub struct Token {
+	pub kind: TokenKind,
+	pub span: Range<usize>
+}
+
+/// Errors returned when converting text into ledger types.
+#[derive(PartialEq, Debug)]
+pub enum WealthError {
+	/// The parser rejected the input.
+	Parse(String),
+	/// The input parsed, but text was left over afterwards.
+	TrailingInput(String),
+	/// An indented posting line on the given line number appeared before any
+	/// transaction header.
+	OrphanPosting(i32),
+	/// The transaction with its header on the given line number does not
+	/// balance.
+	Unbalanced(i32),
+	/// The input has more entries than the configured maximum.
+	TooManyEntries(usize),
+	/// A quantity with more digits than the configured maximum.
+	TooManyDigits(usize),
+	/// A date that isn't on the calendar, with strict dates on.
+	InvalidDate(Date),
+	/// A quoted symbol, opened at the given line and column, with no closing
+	/// quote.
+	UnterminatedQuote(i32, i32),
+	/// The balance assertion on the given line number doesn't match the
+	/// account's running balance.
+	FailedAssertion(i32),
+	/// An account, posted to on the given line number, with no account
+	/// directive.
+	UndeclaredAccount(i32, String),
+	/// A commodity, used on the given line number, with no commodity
+	/// directive.
+	UndeclaredCommodity(i32, String),
+	/// Amounts on the given line number are too large to combine.
+	Overflow(i32)
+}
+
+impl fmt::Display for WealthError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			WealthError::Parse(ref message) => write!(f, "{}", message),
+			WealthError::TrailingInput(ref rest) =>
+				write!(f, "Unexpected trailing input: {:?}", rest),
+			WealthError::OrphanPosting(line) =>
+				write!(f, "Posting on line {} has no transaction header", line),
+			WealthError::Unbalanced(line) =>
+				write!(f, "Transaction on line {} does not balance", line),
+			WealthError::TooManyEntries(max) =>
+				write!(f, "More than {} entries", max),
+			WealthError::TooManyDigits(max) =>
+				write!(f, "Quantity has more than {} digits", max),
+			WealthError::InvalidDate(ref date) =>
+				write!(f, "Invalid date {}", date),
+			WealthError::UnterminatedQuote(line, column) =>
+				write!(f, "Unterminated quoted symbol starting at line {}, column {}", line, column),
+			WealthError::FailedAssertion(line) =>
+				write!(f, "Balance assertion on line {} failed", line),
+			WealthError::UndeclaredAccount(line, ref account) =>
+				write!(f, "Account {} on line {} is not declared", account, line),
+			WealthError::UndeclaredCommodity(line, ref symbol) =>
+				write!(f, "Commodity {} on line {} is not declared", symbol, line),
+			WealthError::Overflow(line) =>
+				write!(f, "Amount on line {} is too large", line)
+		}
+	}
+}
+
+impl Error for WealthError {}
+
+
+/// Errors returned when inferring the missing amount in a transaction. Each
+/// holds the line number of the transaction's header.
+#[derive(PartialEq, Debug)]
+pub enum BalanceError {
+	/// More than one posting is missing its amount.
+	TooManyMissingAmounts(i32),
+	/// The missing amount would have to cover more than one commodity.
+	MultipleCommodities(i32),
+	/// No amount is missing and the postings don't sum to zero.
+	Unbalanced(i32),
+	/// The amount on the given line number, priced by its cost or added to
+	/// the others in its commodity, is too large to hold.
+	Overflow(i32)
+}
+
+impl fmt::Display for BalanceError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			BalanceError::TooManyMissingAmounts(line) =>
+				write!(f, "Transaction on line {} has more than one posting without an amount", line),
+			BalanceError::MultipleCommodities(line) =>
+				write!(f, "Transaction on line {} needs more than one commodity to balance", line),
+			BalanceError::Unbalanced(line) =>
+				write!(f, "Transaction on line {} does not balance", line),
+			BalanceError::Overflow(line) =>
+				write!(f, "Amount on line {} is too large to balance", line)
+		}
+	}
+}
+
+/// Error returned when combining amounts of two different commodities.
+#[derive(PartialEq, Debug)]
+pub struct CommodityMismatch {
+	pub left: Symbol,
+	pub right: Symbol
+}
+
+impl fmt::Display for CommodityMismatch {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Cannot combine amounts in {} and {}", self.left, self.right)
+	}
+}
+
+/// Where and why parsing failed, for reporting to users.
+#[derive(PartialEq, Debug)]
+pub struct ParseFailure {
+	pub line: i32,
+	pub column: i32,
+	pub message: String
+}
+
+impl<'a> From<ParseError<&'a str>> for ParseFailure {
+	fn from(err: ParseError<&'a str>) -> ParseFailure {
+		// combine's own message leads with the position, which we keep separately
+		let text = err.to_string();
+		let message = text.lines()
+			.skip(1)
+			.map(str::trim)
+			.filter(|line| !line.is_empty())
+			.collect::<Vec<_>>()
+			.join("; ");
+		ParseFailure {
+			line: err.position.line,
+			column: err.position.column,
+			message: message
+		}
+	}
+}
+
+impl fmt::Display for ParseFailure {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Parse error at line {}, column {}: {}", self.line, self.column, self.message)
+	}
+}
+
+/// Errors returned when reading and parsing a journal file.
+#[derive(Debug)]
+pub enum JournalError {
+	/// The file could not be read.
+	Io(io::Error),
+	/// The file's contents could not be parsed.
+	Parse(ParseFailure)
+}
+
+impl fmt::Display for JournalError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			JournalError::Io(ref err) => write!(f, "Error reading journal: {}", err),
+			JournalError::Parse(ref failure) => write!(f, "{}", failure)
+		}
+	}
+}
+
+impl From<io::Error> for JournalError {
+	fn from(err: io::Error) -> JournalError {
+		JournalError::Io(err)
+	}
+}
+
+
+
+/// Gets the current line number.
+fn line_number<I>(input: State<I>) -> ParseResult<i32, I>
+where I: Stream<Item=char> {
+	Ok((input.position.line, Consumed::Empty(input)))
+}
+
+#[test]
+fn line_number_test() {
+	let (line_num, remaining_input) = parser(line_number)
+		.parse("hello")
+		.unwrap();
+	assert_eq!(line_num, 1);
+	assert_eq!(remaining_input, "hello");
+}
+
+
+
+/// Gets the current column number.
+fn column_number<I>(input: State<I>) -> ParseResult<i32, I>
+where I: Stream<Item=char> {
+	Ok((input.position.column, Consumed::Empty(input)))
+}
+
+#[test]
+fn column_number_test() {
+	let (column_num, remaining_input) = char('h').with(parser(column_number))
+		.parse("hello")
+		.unwrap();
+	assert_eq!(column_num, 2);
+	assert_eq!(remaining_input, "ello");
+}
+
+
+
+/// Parses at least one whitespace character (space, tab or non-breaking
+/// space).
+fn whitespace<I>(input: State<I>) -> ParseResult<String, I>
+where I: Stream<Item=char> {
+	many1::<String, _>(satisfy(|c| c == ' ' || c == '\t' || c == '\u{00A0}'))
+		.parse_state(input)
+}
+
+#[test]
+fn empty_whitespace_is_error()
+{
+	let result = parser(whitespace)
+		.parse("")
+		.map(|x| x.0);
+	assert!(result.is_err());
+}
+
+#[test]
+fn whitespace_space()
+{
+	let result = parser(whitespace)
+		.parse(" ")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(" ".to_string()));
+}
+
+#[test]
+fn whitespace_tab()
+{
+	let result = parser(whitespace)
+		.parse("\t")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("\t".to_string()));
+}
+
+#[test]
+fn whitespace_non_breaking_space()
+{
+	let result = parser(whitespace)
+		.parse("\u{00A0}")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("\u{00A0}".to_string()));
+}
+
+
+
+/// Parses a Unix, Windows or classic Mac style line ending, normalized to \n
+fn line_ending<I>(input: State<I>) -> ParseResult<String, I>
+where I: Stream<Item=char> {
+	try(crlf())
+		.map(|x: char| x.to_string())
+		.or(newline()
+			.map(|x: char| x.to_string()))
+		.or(char('\r')
+			.map(|_| "\n".to_string()))
+		.parse_state(input)
+}
+
+#[test]
+fn line_ending_unix() {
+	let result = parser(line_ending)
+		.parse("\n")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("\n".to_string()));
+}
+
+#[test]
+fn line_ending_windows() {
+	let result = parser(line_ending)
+		.parse("\r\n")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("\n".to_string()));
+}
+
+#[test]
+fn line_ending_classic_mac() {
+	let result = parser(line_ending)
+		.parse("\r")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("\n".to_string()));
+}
+
+
+
+/// Takes a tuple of digit characters and converts them to an i32
+fn two_digits_to_int((x, y): (char, char)) -> i32 {
+    let x = x.to_digit(10).expect("digit");
+    let y = y.to_digit(10).expect("digit");
+    (x * 10 + y) as i32
+}
+
+#[test]
+fn two_digits_to_int_test() {
+	let result = two_digits_to_int(('2', '7'));
+	assert_eq!(result, 27);
+}
+
+
+
+/// Wrapped parser for parsing two digits. e.g. 17
+fn two_digits<I>() -> FnParser<I, fn (State<I>) -> ParseResult<i32, I>>
+where I: Stream<Item=char> {
+    fn two_digits_<I>(input: State<I>) -> ParseResult<i32, I>
+    where I: Stream<Item=char> {
+        (digit(), digit())
+            .map(two_digits_to_int)
+            .parse_state(input)
+    }
+    parser(two_digits_)
+}
+
+#[test]
+fn two_digits_test() {
+	let result = two_digits()
+		.parse("09")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(9));
+}
+
+
+
+/// The date separator written as `c`.
+fn date_separator(c: char) -> DateSeparator {
+	if c == '/' {
+		DateSeparator::Slash
+	} else {
+		DateSeparator::Hyphen
+	}
+}
+
+/// Reads one part of a date, e.g. its year, failing rather than overflowing
+/// when there are too many digits.
+fn date_part(digits: &str, part: &str) -> Result<i32, WealthError> {
+	digits.parse()
+		.map_err(|_| WealthError::Parse(format!("Date {} {} is out of range", part, digits)))
+}
+
+/// Parses a date. e.g. 2015-10-17
+fn date<I>(input: State<I>) -> ParseResult<Date, I>
+where I: Stream<Item=char> {
+	(many1::<String, _>(digit()), char('-'), two_digits(), char('-'), two_digits())
+		.and_then(|(year, _, month, _, day)| {
+			Ok::<_, WealthError>(Date {
+				year: date_part(&year, "year")?,
+				month: month,
+				day: day,
+				separator: DateSeparator::Hyphen
+			})
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn date_test() {
+	let result = parser(date)
+		.parse("2015-10-17")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Date {
+		year: 2015,
+		month: 10,
+		day: 17,
+		separator: DateSeparator::Hyphen
+	}));
+}
+
+
+/// Parses a date written without its year, which is taken from `year`.
+/// e.g. 10/17
+fn short_date<I>(year: i32, input: State<I>) -> ParseResult<Date, I>
+where I: Stream<Item=char> {
+	(many1::<String, _>(digit()), char('/').or(char('-')), many1::<String, _>(digit()))
+		.and_then(|(month, separator, day)| {
+			Ok::<_, WealthError>(Date {
+				year: year,
+				month: date_part(&month, "month")?,
+				day: date_part(&day, "day")?,
+				separator: date_separator(separator)
+			})
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn short_date_overflow() {
+	let options = ParserOptions {
+		default_year: Some(2015),
+		..ParserOptions::default()
+	};
+	let result = parse_journal_with("99999999999/17 Payee\n", &options);
+	assert_eq!(result.map_err(|failure| failure.message),
+		Err("Date month 99999999999 is out of range".to_string()));
+}
+
+#[test]
+fn short_date_test() {
+	let result = parser(|input| short_date(2015, input))
+		.parse("10/17")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Date {
+		year: 2015,
+		month: 10,
+		day: 17,
+		separator: DateSeparator::Slash
+	}));
+}
+
+
+
+/// Parses a full date like `date`, also accepting slashes as separators when
+/// the options allow them. e.g. 2015/10/17
+/// Both separators must be the same, so 2015-10/17 is an error.
+fn date_with<I>(options: &ParserOptions, input: State<I>) -> ParseResult<Date, I>
+where I: Stream<Item=char> {
+	let separator = || satisfy(|c| c == '-' || (options.allow_slash_dates && c == '/'));
+	(many1::<String, _>(digit()), separator(), two_digits(), separator(), two_digits())
+		.and_then(|(year, separator, month, second_separator, day)| {
+			if separator != second_separator {
+				return Err(WealthError::Parse(
+					format!("Date separators {} and {} don't match", separator, second_separator)));
+			}
+			Ok(Date {
+				year: date_part(&year, "year")?,
+				month: month,
+				day: day,
+				separator: date_separator(separator)
+			})
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn date_with_slashes() {
+	let allow_slash_dates = ParserOptions {
+		allow_slash_dates: true,
+		..ParserOptions::default()
+	};
+	let result = parser(|input| date_with(&allow_slash_dates, input))
+		.parse("2015/10/17")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Date {
+		year: 2015,
+		month: 10,
+		day: 17,
+		separator: DateSeparator::Slash
+	}));
+
+	let result = parser(|input| date_with(&ParserOptions::default(), input))
+		.parse("2015/10/17");
+	assert!(result.is_err());
+
+	let result = parser(|input| date_with(&allow_slash_dates, input))
+		.parse("2015-10/17");
+	assert!(result.is_err());
+}
+
+#[test]
+fn date_with_missing_or_overflowing_year() {
+	for text in ["-10-17 Payee\n", "99999999999-10-17 Payee\n"].iter() {
+		assert!(parse_journal(text).is_err(), "{:?} should fail", text);
+	}
+	let failure = parse_journal("99999999999-10-17 Payee\n").unwrap_err();
+	assert_eq!(failure.message, "Date year 99999999999 is out of range");
+}
+
+
+
+/// Parses a transaction date. When a default year is set, the year may be
+/// left off. With strict dates on, the date must be on the calendar.
+fn transaction_date<I>(options: &ParserOptions, default_year: Option<i32>, input: State<I>)
+	-> ParseResult<Date, I>
+where I: Stream<Item=char> {
+	let check = |date: Date| {
+		if options.strict_dates && !date.is_valid() {
+			Err(WealthError::InvalidDate(date))
+		} else {
+			Ok(date)
+		}
+	};
+	match default_year {
+		Some(year) => try(parser(|input| date_with(options, input)))
+			.or(parser(|input| short_date(year, input)))
+			.and_then(check)
+			.parse_state(input),
+		None => parser(|input| date_with(options, input))
+			.and_then(check)
+			.parse_state(input)
+	}
+}
+
+#[test]
+fn transaction_date_full_date_with_default_year() {
+	let result = parser(|input| transaction_date(&ParserOptions::default(), Some(2015), input))
+		.parse("2014-12-31")
+		.map(|x| x.0.year);
+	assert_eq!(result, Ok(2014));
+}
+
+#[test]
+fn transaction_date_short_date_needs_default_year() {
+	let result = parser(|input| transaction_date(&ParserOptions::default(), None, input))
+		.parse("10/17");
+	assert!(result.is_err());
+}
+
+#[test]
+fn transaction_date_strict_dates() {
+	let strict_dates = ParserOptions {
+		strict_dates: true,
+		..ParserOptions::default()
+	};
+	let lenient = parser(|input| transaction_date(&ParserOptions::default(), None, input))
+		.parse("2015-02-30")
+		.map(|x| x.0.day);
+	assert_eq!(lenient, Ok(30));
+	let strict = parser(|input| transaction_date(&strict_dates, None, input))
+		.parse("2015-02-30");
+	assert!(strict.is_err());
+	let strict = parser(|input| transaction_date(&strict_dates, Some(2016), input))
+		.parse("02/29")
+		.map(|x| x.0.day);
+	assert_eq!(strict, Ok(29));
+}
+
+
+
+/// Parses transaction status token. e.g. * (cleared) or ! (uncleared)
+fn status<I>(input: State<I>) -> ParseResult<TransactionStatus, I>
+where I: Stream<Item=char> {
+	char('*')
+		.map(|_| TransactionStatus::Cleared)
+		.or(char('!').map(|_| TransactionStatus::Uncleared))
+		.parse_state(input)
+}
+
+#[test]
+fn status_cleared() {
+	let result = parser(status)
+		.parse("*")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(TransactionStatus::Cleared));
+}
+
+#[test]
+fn status_uncleared() {
+	let result = parser(status)
+		.parse("!")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(TransactionStatus::Uncleared));
+}
+
+
+
+/// Parses transaction code. e.g. (cheque #802)
+fn code<I>(input: State<I>) -> ParseResult<String, I>
+where I: Stream<Item=char> {
+	between(char('('), char(')'), parser(code_text))
+		.parse_state(input)
+}
+
+/// Parses the text inside a transaction code, including any balanced nested
+/// parentheses. e.g. ref (inner) x
+fn code_text<I>(input: State<I>) -> ParseResult<String, I>
+where I: Stream<Item=char> {
+	let plain = many1::<String, _>(satisfy(|c| c != '\r' && c != '\n' && c != '(' && c != ')'));
+	let nested = (char('('), parser(code_text), char(')'))
+		.map(|(_, inner, _)| format!("({})", inner));
+	many::<Vec<String>, _>(plain.or(nested))
+		.map(|parts| parts.concat())
+		.parse_state(input)
+}
+
+#[test]
+fn empty_code() {
+	let result = parser(code)
+		.parse("()")
+		.map(|x| x.0);
+	assert!(result.unwrap().is_empty());
+}
+
+#[test]
+fn short_code() {
+	let result = parser(code)
+		.parse("(89)")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("89".to_string()));
+}
+
+#[test]
+fn long_code() {
+	let result = parser(code)
+		.parse("(conf# abc-123-DEF)")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("conf# abc-123-DEF".to_string()));
+}
+
+#[test]
+fn nested_parentheses_code() {
+	let result = parser(code)
+		.parse("(ref (inner) x)")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("ref (inner) x".to_string()));
+}
+
+#[test]
+fn unbalanced_code_is_error() {
+	let result = parser(code)
+		.parse("(oops")
+		.map(|x| x.0);
+	assert!(result.is_err());
+}
+
+
+
+/// Parses a payee. A payee wrapped in double quotes may contain semicolons;
+/// the quotes are dropped. An unquoted payee ends at the first semicolon.
+fn payee<I>(input: State<I>) -> ParseResult<String,I>
+where I: Stream<Item=char> {
+	try(parser(quoted_payee))
+		.or(many1(satisfy(|c| c != ';' && c != '\n' && c != '\r')))
+		.parse_state(input)
+}
+
+/// Parses a payee wrapped in double quotes, which must be all there is up to
+/// any comment. e.g. "Pay; ee"
+fn quoted_payee<I>(input: State<I>) -> ParseResult<String,I>
+where I: Stream<Item=char> {
+	(
+		char('\"'),
+		many1(satisfy(|c| c != '\"' && c != '\r' && c != '\n')),
+		char('\"'),
+		optional(parser(whitespace)),
+		not_followed_by(satisfy(|c| c != ';' && c != '\n' && c != '\r'))
+	)
+		.map(|(_, payee, _, _, _)| payee)
+		.parse_state(input)
+}
+
+#[test]
+fn quoted_payee_with_semicolon() {
+	let result = parser(payee)
+		.parse("\"Pay; ee\" ;comment")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("Pay; ee".to_string()));
+}
+
+#[test]
+fn unquoted_payee_ends_at_semicolon() {
+	let result = parser(payee)
+		.parse("Pay; ee")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("Pay".to_string()));
+}
+
+#[test]
+fn payee_starting_with_quote_is_unquoted() {
+	let result = parser(payee)
+		.parse("\"Bob\" Smith")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("\"Bob\" Smith".to_string()));
+}
+
+#[test]
+fn empty_payee_is_error() {
+	let result = parser(payee)
+		.parse("")
+		.map(|x| x.0);
+	assert!(result.is_err());
+}
+
+#[test]
+fn single_character_payee() {
+	let result = parser(payee)
+		.parse("Z")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("Z".to_string()));
+}
+
+#[test]
+fn short_payee() {
+	let result = parser(payee)
+		.parse("WonderMart")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("WonderMart".to_string()));
+}
+
+#[test]
+fn long_payee() {
+	let result = parser(payee)
+		.parse("WonderMart - groceries, kitchen supplies (pot), light bulbs")
+		.map(|x| x.0);
+	assert_eq!(result,
+		Ok("WonderMart - groceries, kitchen supplies (pot), light bulbs".to_string()));
+}
+
+#[test]
+fn very_long_payee() {
+	let long_payee = "WonderMart - groceries, kitchen supplies (pot), light bulbs ".repeat(1000);
+	let text = format!("{};comment", long_payee);
+	let result = parser(payee)
+		.parse(&text[..])
+		.map(|x| x.0);
+	assert_eq!(result, Ok(long_payee));
+}
+
+
+
+/// Parses a comment.
+fn comment<I>(input: State<I>) -> ParseResult<String,I>
+where I: Stream<Item=char> {
+	(char(';'), many(satisfy(|c| c != '\r' && c != '\n')))
+		.map(|(_, payee)| payee)
+		.parse_state(input)
+}
+
+#[test]
+fn empty_comment() {
+	let result = parser(comment)
+		.parse(";")
+		.map(|x| x.0);
+	assert!(result.unwrap().is_empty());
+}
+
+#[test]
+fn comment_no_leading_space() {
+	let result = parser(comment)
+		.parse(";Comment")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("Comment".to_string()));
+}
+
+#[test]
+fn comment_with_leading_space() {
+	let result = parser(comment)
+		.parse("; Comment")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(" Comment".to_string()));
+}
+
+
+
+/// Parses a top-level comment line, which starts with any of `;`, `#`, `%`,
+/// `|` or `*`.
+fn line_comment<I>(input: State<I>) -> ParseResult<String,I>
+where I: Stream<Item=char> {
+	(satisfy(|c| ";#%|*".contains(c)), many(satisfy(|c| c != '\r' && c != '\n')))
+		.map(|(_, text)| text)
+		.parse_state(input)
+}
+
+#[test]
+fn line_comment_lead_characters() {
+	for marker in [";", "#", "%", "|", "*"].iter() {
+		let text = format!("{} Comment", marker);
+		let result = parser(line_comment)
+			.parse(&text[..])
+			.map(|x| x.0);
+		assert_eq!(result, Ok(" Comment".to_string()));
+	}
+}
+
+#[test]
+fn line_comment_other_character_is_error() {
+	let result = parser(line_comment)
+		.parse("Comment")
+		.map(|x| x.0);
+	assert!(result.is_err());
+}
+
+
+
+/// Extracts metadata from an already parsed comment: flag-style tags
+/// (e.g. :vacation:reimbursable:) or a typed tag (e.g. trip: Paris 2015).
+pub fn parse_comment_tags(comment: &str) -> CommentMeta {
+	let mut meta = CommentMeta::default();
+	let text = comment.trim();
+
+	if text.len() > 1 && text.starts_with(':') && text.ends_with(':') {
+		meta.tags = text[1..text.len() - 1]
+			.split(':')
+			.filter(|tag| !tag.is_empty())
+			.map(|tag| tag.to_string())
+			.collect();
+	} else if let Some(index) = text.find(':') {
+		let key = &text[..index];
+		if !key.is_empty() && !key.contains(char::is_whitespace) {
+			let value = text[index + 1..].trim();
+			meta.values.push((key.to_string(), value.to_string()));
+		}
+	}
+
+	meta
+}
+
+impl CommentMeta {
+	/// The value of the typed tag with the given key, if there is one.
+	pub fn value(&self, key: &str) -> Option<&str> {
+		self.values.iter()
+			.find(|pair| pair.0 == key)
+			.map(|pair| pair.1.as_str())
+	}
+}
+
+#[test]
+fn comment_tags_flags() {
+	let result = parse_comment_tags(" :vacation:reimbursable:");
+	assert_eq!(result, CommentMeta {
+		tags: vec![
+			"vacation".to_string(),
+			"reimbursable".to_string()
+		],
+		values: vec![]
+	});
+}
+
+#[test]
+fn comment_tags_key_value() {
+	let result = parse_comment_tags(" trip: Paris 2015");
+	assert_eq!(result, CommentMeta {
+		tags: vec![],
+		values: vec![("trip".to_string(), "Paris 2015".to_string())]
+	});
+}
+
+#[test]
+fn comment_tags_plain_comment() {
+	let result = parse_comment_tags(" Bought a new kettle");
+	assert_eq!(result, CommentMeta {
+		tags: vec![],
+		values: vec![]
+	});
+}
+
+
+
+/// Parses a transaction header
+#[cfg(test)]
+fn header<I>(input: State<I>) -> ParseResult<Header,I>
+where I: Stream<Item=char> {
+	header_in_year(&ParserOptions::default(), None, input)
+}
+
+/// Parses a transaction header, whose date may leave off the year when a
+/// default year is set.
+fn header_in_year<I>(options: &ParserOptions, default_year: Option<i32>, input: State<I>)
+	-> ParseResult<Header,I>
+where I: Stream<Item=char> {
+	(
+		parser(line_number),
+		parser(|input| transaction_date(options, default_year, input)),
+		optional(char('=').with(parser(|input| transaction_date(options, default_year, input))))
+			.skip(parser(whitespace)),
+		optional(parser(status).skip(parser(whitespace))),
+		optional(parser(code).skip(parser(whitespace))),
+		parser(payee),
+		optional(parser(comment))
+	)
+		.map(|(line_num, date, effective_date, opt_status, code, payee, opt_comment)| {
+			Header {
+				line_number: line_num,
+				date: date,
+				effective_date: effective_date,
+				status: opt_status.unwrap_or(TransactionStatus::Unmarked),
+				code: code,
+				payee: if options.trim_payees {
+					payee.trim_end().to_string()
+				} else {
+					payee
+				},
+				comment: opt_comment
+			}
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn full_header() {
+	let result = parser(header)
+		.parse("2015-10-20 * (conf# abc-123) Payee ;Comment")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Header {
+		line_number: 1,
+		date: Date {
+			year: 2015,
+			month: 10,
+			day: 20,
+			separator: DateSeparator::Hyphen
+		},
+		effective_date: None,
+		status: TransactionStatus::Cleared,
+		code: Some("conf# abc-123".to_string()),
+		payee: "Payee ".to_string(),
+		comment: Some("Comment".to_string())
+	}));
+}
+
+#[test]
+fn header_trim_payees() {
+	let trim_payees = ParserOptions {
+		trim_payees: true,
+		..ParserOptions::default()
+	};
+	let result = parser(|input| header_in_year(&trim_payees, None, input))
+		.parse("2015-10-20 * Grocery  Store \t;Comment")
+		.map(|x| x.0.payee);
+	assert_eq!(result, Ok("Grocery  Store".to_string()));
+}
+
+#[test]
+fn header_with_code_and_no_comment() {
+	let result = parser(header)
+		.parse("2015-10-20 ! (conf# abc-123) Payee")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Header {
+		line_number: 1,
+		date: Date {
+			year: 2015,
+			month: 10,
+			day: 20,
+			separator: DateSeparator::Hyphen
+		},
+		effective_date: None,
+		status: TransactionStatus::Uncleared,
+		code: Some("conf# abc-123".to_string()),
+		payee: "Payee".to_string(),
+		comment: None
+	}));
+}
+
+#[test]
+fn header_with_comment_and_no_code() {
+	let result = parser(header)
+		.parse("2015-10-20 * Payee ;Comment")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Header {
+		line_number: 1,
+		date: Date {
+			year: 2015,
+			month: 10,
+			day: 20,
+			separator: DateSeparator::Hyphen
+		},
+		effective_date: None,
+		status: TransactionStatus::Cleared,
+		code: None,
+		payee: "Payee ".to_string(),
+		comment: Some("Comment".to_string())
+	}));
+}
+
+#[test]
+fn header_with_no_code_or_comment() {
+	let result = parser(header)
+		.parse("2015-10-20 * Payee")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Header {
+		line_number: 1,
+		date: Date {
+			year: 2015,
+			month: 10,
+			day: 20,
+			separator: DateSeparator::Hyphen
+		},
+		effective_date: None,
+		status: TransactionStatus::Cleared,
+		code: None,
+		payee: "Payee".to_string(),
+		comment: None
+	}));
+}
+
+
+
+#[test]
+fn header_with_no_status() {
+	let result = parser(header)
+		.parse("2015-10-20 Payee")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Header {
+		line_number: 1,
+		date: Date {
+			year: 2015,
+			month: 10,
+			day: 20,
+			separator: DateSeparator::Hyphen
+		},
+		effective_date: None,
+		status: TransactionStatus::Unmarked,
+		code: None,
+		payee: "Payee".to_string(),
+		comment: None
+	}));
+}
+
+#[test]
+fn header_with_effective_date() {
+	let result = parser(header)
+		.parse("2015-10-20=2015-10-22 * Payee")
+		.map(|x| x.0);
+	let header = result.unwrap();
+	assert_eq!(header.effective_date, Some(Date {
+		year: 2015,
+		month: 10,
+		day: 22,
+		separator: DateSeparator::Hyphen
+	}));
+	assert_eq!(header.to_string(), "2015-10-20=2015-10-22 * Payee");
+}
+
+
+
+impl Date {
+	/// Whether the date is on the calendar, e.g. not February 30th.
+	pub fn is_valid(&self) -> bool {
+		self.month >= 1 && self.month <= 12
+			&& self.day >= 1 && self.day <= days_in_month(self.year, self.month)
+	}
+
+	/// The day after this one.
+	pub fn next_day(&self) -> Date {
+		if self.day < days_in_month(self.year, self.month) {
+			Date {
+				year: self.year,
+				month: self.month,
+				day: self.day + 1,
+				separator: self.separator
+			}
+		} else if self.month < 12 {
+			Date {
+				year: self.year,
+				month: self.month + 1,
+				day: 1,
+				separator: self.separator
+			}
+		} else {
+			Date {
+				year: self.year + 1,
+				month: 1,
+				day: 1,
+				separator: self.separator
+			}
+		}
+	}
+
+	/// The first day of the month after this date's.
+	pub fn next_month(&self) -> Date {
+		if self.month < 12 {
+			Date {
+				year: self.year,
+				month: self.month + 1,
+				day: 1,
+				separator: self.separator
+			}
+		} else {
+			Date {
+				year: self.year + 1,
+				month: 1,
+				day: 1,
+				separator: self.separator
+			}
+		}
+	}
+
+	/// Every day from `from` to `to`, both included. Empty if `to` is before
+	/// `from`.
+	pub fn iter_days(from: &Date, to: &Date) -> impl Iterator<Item=Date> {
+		let to = to.clone();
+		iter::successors(Some(from.clone()), |date| Some(date.next_day()))
+			.take_while(move |date| *date <= to)
+	}
+
+	/// The first day of every month from the month of `from` to the month of
+	/// `to`, both included. Empty if `to` is before `from`.
+	pub fn iter_months(from: &Date, to: &Date) -> impl Iterator<Item=Date> {
+		let first = Date {
+			year: from.year,
+			month: from.month,
+			day: 1,
+			separator: from.separator
+		};
+		let last = Date {
+			year: to.year,
+			month: to.month,
+			day: 1,
+			separator: to.separator
+		};
+		iter::successors(Some(first), |date| Some(date.next_month()))
+			.take_while(move |date| *date <= last)
+	}
+}
+
+/// The number of days in the given month, accounting for leap years.
+fn days_in_month(year: i32, month: i32) -> i32 {
+	match month {
+		2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+		2 => 28,
+		4 | 6 | 9 | 11 => 30,
+		_ => 31
+	}
+}
+
+#[test]
+fn date_iter_days_across_month_boundary() {
+	let from = Date { year: 2016, month: 2, day: 27, separator: DateSeparator::Hyphen };
+	let to = Date { year: 2016, month: 3, day: 2, separator: DateSeparator::Hyphen };
+	let days: Vec<String> = Date::iter_days(&from, &to).map(|date| date.to_string()).collect();
+	assert_eq!(days, vec!["2016-02-27", "2016-02-28", "2016-02-29", "2016-03-01", "2016-03-02"]);
+	assert_eq!(Date::iter_days(&to, &from).count(), 0);
+}
+
+#[test]
+fn date_iter_months_across_year_boundary() {
+	let from = Date { year: 2015, month: 11, day: 15, separator: DateSeparator::Hyphen };
+	let to = Date { year: 2016, month: 2, day: 3, separator: DateSeparator::Hyphen };
+	let months: Vec<String> = Date::iter_months(&from, &to).map(|date| date.to_string()).collect();
+	assert_eq!(months, vec!["2015-11-01", "2015-12-01", "2016-01-01", "2016-02-01"]);
+}
+
+impl PartialEq for Date {
+	fn eq(&self, other: &Date) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for Date {}
+
+impl PartialOrd for Date {
+	fn partial_cmp(&self, other: &Date) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for Date {
+	fn cmp(&self, other: &Date) -> Ordering {
+		(self.year, self.month, self.day).cmp(&(other.year, other.month, other.day))
+	}
+}
+
+impl fmt::Display for Date {
+	/// Writes the date with a four digit year and two digit month and day,
+	/// separated as it was written.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let separator = match self.separator {
+			DateSeparator::Hyphen => '-',
+			DateSeparator::Slash => '/'
+		};
+		write!(f, "{:04}{}{:02}{}{:02}", self.year, separator, self.month, separator, self.day)
+	}
+}
+
+impl fmt::Display for Header {
+	/// Writes the header back out as a ledger transaction line.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.date)?;
+		if let Some(ref effective_date) = self.effective_date {
+			write!(f, "={}", effective_date)?;
+		}
+		match self.status {
+			TransactionStatus::Cleared => write!(f, " *")?,
+			TransactionStatus::Uncleared => write!(f, " !")?,
+			TransactionStatus::Unmarked => {}
+		}
+		if let Some(ref code) = self.code {
+			write!(f, " ({})", code)?;
+		}
+		if self.payee.contains(';') {
+			write!(f, " \"{}\"", self.payee)?;
+		} else {
+			write!(f, " {}", self.payee)?;
+		}
+		if let Some(ref comment) = self.comment {
+			write!(f, ";{}", comment)?;
+		}
+		Ok(())
+	}
+}
+
+#[test]
+fn date_display_keeps_separator() {
+	let options = ParserOptions {
+		allow_slash_dates: true,
+		..ParserOptions::default()
+	};
+	for text in ["2015/10/17", "2015-10-17"].iter() {
+		let result = parser(|input| date_with(&options, input))
+			.parse(*text)
+			.map(|x| x.0.to_string());
+		assert_eq!(result, Ok(text.to_string()));
+	}
+}
+
+#[test]
+fn date_equality_ignores_separator() {
+	let hyphen = parser(date).parse("2015-10-17").map(|x| x.0);
+	let slash = parser(|input| short_date(2015, input)).parse("10/17").map(|x| x.0);
+	assert_eq!(hyphen, slash);
+}
+
+#[test]
+fn date_display_zero_padded() {
+	let date = Date {
+		year: 2015,
+		month: 1,
+		day: 2,
+		separator: DateSeparator::Hyphen
+	};
+	assert_eq!(date.to_string(), "2015-01-02");
+}
+
+#[test]
+fn date_ordering() {
+	let date = |year, month, day| Date {
+		year: year,
+		month: month,
+		day: day,
+		separator: DateSeparator::Hyphen
+	};
+	assert!(date(2015, 1, 2) < date(2015, 2, 1));
+	assert!(date(2014, 12, 31) < date(2015, 1, 1));
+	assert_eq!(date(2015, 10, 17).cmp(&date(2015, 10, 17)), std::cmp::Ordering::Equal);
+
+	let mut dates = vec![date(2015, 10, 17), date(2014, 3, 9), date(2015, 2, 28), date(2015, 10, 1)];
+	dates.sort();
+	assert_eq!(dates, vec![date(2014, 3, 9), date(2015, 2, 28), date(2015, 10, 1), date(2015, 10, 17)]);
+}
+
+#[test]
+fn header_display_round_trip() {
+	let text = "2015-10-20 * (conf# abc-123) Payee ;Comment";
+	let result = parser(header)
+		.parse(text)
+		.map(|x| x.0.to_string());
+	assert_eq!(result, Ok(text.to_string()));
+}
+
+#[test]
+fn header_display_round_trip_quoted_payee() {
+	let text = "2015-10-20 * \"Pay; ee\";Comment";
+	let result = parser(header)
+		.parse(text)
+		.map(|x| x.0.to_string());
+	assert_eq!(result, Ok(text.to_string()));
+}
+
+
+
+/// Parses a sub-account name: alphanumeric words separated by single spaces.
+/// Two or more spaces end the name, as they separate an account from its
+/// amount.
+fn sub_account<I>(input: State<I>) -> ParseResult<String,I>
+where I: Stream<Item=char> {
+	(
+		many1::<String, _>(alpha_num()),
+		many::<Vec<String>, _>(try(char(' ').with(many1(alpha_num()))))
+	)
+		.map(|(first, rest)| {
+			let mut sub_account = first;
+			for word in rest {
+				sub_account.push(' ');
+				sub_account.push_str(&word);
+			}
+			sub_account
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn sub_account_alphanumeric() {
+	let result = parser(sub_account)
+		.parse("AZaz09")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("AZaz09".to_string()));
+}
+
+#[test]
+fn sub_account_can_start_with_digits() {
+	let result = parser(sub_account)
+		.parse("123abcABC")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("123abcABC".to_string()));
+}
+
+#[test]
+fn sub_account_with_single_spaces() {
+	let result = parser(sub_account)
+		.parse("Emergency Fund")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("Emergency Fund".to_string()));
+}
+
+#[test]
+fn sub_account_ends_at_two_spaces() {
+	let result = parser(sub_account)
+		.parse("Emergency  Fund");
+	assert_eq!(result, Ok(("Emergency".to_string(), "  Fund")));
+}
+
+
+
+/// Parses an account, made up of sub-accounts separated by the configured
+/// account separator (a colon by default). Every separator must be followed
+/// by a sub-account, so Expenses::Food and Expenses: are errors.
+fn account<I>(options: &ParserOptions, input: State<I>) -> ParseResult<Vec<String>,I>
+where I: Stream<Item=char> {
+	sep_by1(
+		parser(sub_account).expected("sub-account name"),
+		char(options.account_separator))
+		.parse_state(input)
+}
+
+#[test]
+fn account_multiple_level() {
+	let result = parser(|input| account(&ParserOptions::default(), input))
+		.parse("Expenses:Food:Groceries")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(vec![
+		"Expenses".to_string(),
+		"Food".to_string(),
+		"Groceries".to_string()
+	]));
+}
+
+#[test]
+fn account_single_level() {
+	let result = parser(|input| account(&ParserOptions::default(), input))
+		.parse("Expenses")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(vec!["Expenses".to_string()]));
+}
+
+#[test]
+fn account_empty_sub_account() {
+	for text in ["Expenses::Food", "Expenses:"].iter() {
+		let result = parser(|input| account(&ParserOptions::default(), input))
+			.parse(*text)
+			.map(|x| x.0);
+		assert!(result.is_err(), "{} should fail", text);
+	}
+
+	let failure = parse_journal("\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses::Food  $45.00\n\
+		\tAssets:Cash\n\
+	").unwrap_err();
+	assert_eq!((failure.line, failure.column), (2, 11));
+	assert_eq!(failure.message, "Unexpected ':'; Expected 'sub-account name'");
+}
+
+#[test]
+fn account_custom_separator() {
+	let options = ParserOptions {
+		account_separator: '/',
+		..ParserOptions::default()
+	};
+	let result = parser(|input| account(&options, input))
+		.parse("Expenses/Food/Groceries")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(vec![
+		"Expenses".to_string(),
+		"Food".to_string(),
+		"Groceries".to_string()
+	]));
+}
+
+
+
+/// Parses the account of a posting, which is wrapped in parentheses for a
+/// virtual posting or brackets for a balanced virtual posting.
+fn posting_account<I>(options: &ParserOptions, input: State<I>)
+	-> ParseResult<(PostingType, Vec<String>), I>
+where I: Stream<Item=char> {
+	between(char('('), char(')'), parser(|input| account(options, input)))
+		.map(|account| (PostingType::Virtual, account))
+		.or(between(char('['), char(']'), parser(|input| account(options, input)))
+			.map(|account| (PostingType::BalancedVirtual, account)))
+		.or(parser(|input| account(options, input))
+			.map(|account| (PostingType::Real, account)))
+		.parse_state(input)
+}
+
+#[test]
+fn posting_account_virtual() {
+	let result = parser(|input| posting_account(&ParserOptions::default(), input))
+		.parse("(Assets:Cash)")
+		.map(|x| x.0);
+	assert_eq!(result, Ok((PostingType::Virtual, vec![
+		"Assets".to_string(),
+		"Cash".to_string()
+	])));
+}
+
+#[test]
+fn posting_account_balanced_virtual() {
+	let result = parser(|input| posting_account(&ParserOptions::default(), input))
+		.parse("[Equity:Budget]")
+		.map(|x| x.0);
+	assert_eq!(result, Ok((PostingType::BalancedVirtual, vec![
+		"Equity".to_string(),
+		"Budget".to_string()
+	])));
+}
+
+#[test]
+fn posting_account_real() {
+	let result = parser(|input| posting_account(&ParserOptions::default(), input))
+		.parse("Assets:Cash")
+		.map(|x| x.0);
+	assert_eq!(result, Ok((PostingType::Real, vec![
+		"Assets".to_string(),
+		"Cash".to_string()
+	])));
+}
+
+
+
+/// Parses a numeric quantity written like 1,234.56. Digits may be grouped
+/// with commas or with non-breaking spaces, both of which are dropped from the
+/// result.
+fn quantity<I>(input: State<I>) -> ParseResult<Decimal,I>
+where I: Stream<Item=char> {
+	quantity_in(NumberFormat::DECIMAL_POINT, input)
+}
+
+/// Parses a numeric quantity written like 1.234,56. Digits may be grouped
+/// with periods or with non-breaking spaces.
+#[cfg(test)]
+fn quantity_eu<I>(input: State<I>) -> ParseResult<Decimal,I>
+where I: Stream<Item=char> {
+	quantity_in(NumberFormat::DECIMAL_COMMA, input)
+}
+
+/// Parses a numeric quantity in the given number format. At most one decimal
+/// separator is allowed, and a separator must be followed by a digit.
+/// Fractional digits may only be grouped when the format allows it, and
+/// parsing fails as soon as there are more digits than the format's maximum.
+fn quantity_in<I>(format: NumberFormat, input: State<I>) -> ParseResult<Decimal,I>
+where I: Stream<Item=char> {
+	let (group_separator, decimal_separator) = format.separators();
+	let fractional_grouping = format.fractional_grouping;
+	let count = Cell::new(0);
+	let counted_digit = || digit().and_then(|digit| {
+		count.set(count.get() + 1);
+		if count.get() > format.max_digits {
+			Err(WealthError::TooManyDigits(format.max_digits))
+		} else {
+			Ok(digit)
+		}
+	});
+	(
+		optional(char('-'))
+			.map(|x| {
+				match x {
+					Some(_) => "-".to_string(),
+					None => "".to_string()
+				}
+			}),
+		counted_digit(),
+		many::<String, _>(
+			counted_digit()
+				.or(char(group_separator).with(counted_digit()))
+				.or(try(char('\u{00A0}').with(counted_digit())))
+		),
+		optional((
+			char(decimal_separator),
+			counted_digit(),
+			many::<String, _>(
+				counted_digit()
+					.or(satisfy(move |c| fractional_grouping && c == group_separator).with(counted_digit()))
+			)
+		))
+			.map(|x| {
+				match x {
+					Some((_, first_digit, fraction)) => format!(".{}{}", first_digit, fraction),
+					None => "".to_string()
+				}
+			})
+			.skip(not_followed_by(satisfy(|c| c == '.' || c == ',')))
+	)
+		.and_then(|(neg_sign, first_digit, digits, fraction)| {
+			let qty = format!("{}{}{}{}",
+				neg_sign,
+				first_digit,
+				digits,
+				fraction);
+			Decimal::from_str(&qty)
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn quantity_negative_no_fractional_part()
+{
+	let result = parser(quantity)
+		.parse("-1110")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Decimal::new(-1110, 0)));
+}
+
+#[test]
+fn quantity_positive_no_fractional_part()
+{
+	let result = parser(quantity)
+		.parse("2,314")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Decimal::new(2314, 0)));
+}
+
+#[test]
+fn quantity_negative_with_fractional_part()
+{
+	let result = parser(quantity)
+		.parse("-1,110.38")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Decimal::new(-111038, 2)));
+}
+
+#[test]
+fn quantity_positive_with_fractional_part()
+{
+	let result = parser(quantity)
+		.parse("24521.793")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Decimal::new(24521793, 3)));
+}
+
+#[test]
+fn quantity_multiple_decimal_points_is_error()
+{
+	let result = parser(quantity)
+		.parse("1.2.3")
+		.map(|x| x.0);
+	assert!(result.is_err());
+}
+
+#[test]
+fn quantity_consecutive_decimal_points_is_error()
+{
+	let result = parser(quantity)
+		.parse("1..2")
+		.map(|x| x.0);
+	assert!(result.is_err());
+}
+
+#[test]
+fn quantity_trailing_separator_is_error()
+{
+	let result = parser(quantity)
+		.parse("1,")
+		.map(|x| x.0);
+	assert!(result.is_err());
+}
+
+#[test]
+fn quantity_grouped_with_fractional_part()
+{
+	let result = parser(quantity)
+		.parse("1,234.56")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Decimal::new(123456, 2)));
+}
+
+#[test]
+fn quantity_non_breaking_space_grouping()
+{
+	let result = parser(quantity)
+		.parse("1\u{00A0}000.00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Decimal::new(100000, 2)));
+}
+
+#[test]
+fn quantity_eu_with_fractional_part()
+{
+	let result = parser(quantity_eu)
+		.parse("1.234,56")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Decimal::new(123456, 2)));
+}
+
+#[test]
+fn quantity_eu_negative()
+{
+	let result = parser(quantity_eu)
+		.parse("-1.000,00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Decimal::new(-100000, 2)));
+}
+
+#[test]
+fn quantity_eu_non_breaking_space_grouping()
+{
+	let result = parser(quantity_eu)
+		.parse("1\u{00A0}000,00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Decimal::new(100000, 2)));
+}
+
+#[test]
+fn quantity_eu_decimal_comma_without_grouping()
+{
+	let result = parser(quantity_eu)
+		.parse("5,42")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Decimal::new(542, 2)));
+}
+
+#[test]
+fn quantity_eu_grouped_with_fractional_part()
+{
+	let result = parser(quantity_eu)
+		.parse("5.420,00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Decimal::new(542000, 2)));
+}
+
+#[test]
+fn quantity_digit_limit()
+{
+	let text = "1".repeat(28);
+	let result = parser(quantity)
+		.parse(&text[..])
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Decimal::from_str(&text).unwrap()));
+
+	let text = "1".repeat(100);
+	let result = parser(quantity)
+		.parse(&text[..]);
+	assert!(result.unwrap_err().to_string().contains("Quantity has more than 64 digits"));
+
+	let text = "1".repeat(10000);
+	let result = parser(quantity)
+		.parse(&text[..]);
+	assert!(result.unwrap_err().to_string().contains("Quantity has more than 64 digits"));
+
+	let format = NumberFormat {
+		max_digits: 8,
+		..NumberFormat::DECIMAL_POINT
+	};
+	let result = parser(|input| quantity_in(format, input))
+		.parse("1,234.56789");
+	assert!(result.is_err());
+}
+
+#[test]
+fn quantity_fractional_grouping()
+{
+	let format = NumberFormat {
+		fractional_grouping: true,
+		..NumberFormat::DECIMAL_POINT
+	};
+	let result = parser(|input| quantity_in(format, input))
+		.parse("1,234.567,8")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Decimal::new(12345678, 4)));
+
+	let result = parser(quantity)
+		.parse("1,234.567,8")
+		.map(|x| x.0);
+	assert!(result.is_err());
+}
+
+#[test]
+fn quantity_comma_is_grouping_under_decimal_point()
+{
+	let result = parser(quantity)
+		.parse("5,420")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Decimal::new(5420, 0)));
+}
+
+
+
+/// Parses a quoted symbol
+fn quoted_symbol<I>(input: State<I>) -> ParseResult<Symbol, I>
+where I: Stream<Item=char> {
+	(
+		parser(line_number),
+		parser(column_number),
+		char('\"'),
+		many1(satisfy(|c| c != '\"' && c != '\r' && c != '\n')),
+		optional(char('\"'))
+	)
+		.and_then(|(line_num, column_num, _, symbol, close)| match close {
+			Some(_) => Ok(Symbol {
+				value: symbol,
+				quoted: true
+			}),
+			None => Err(WealthError::UnterminatedQuote(line_num, column_num))
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn quoted_symbol_unterminated() {
+	let result = parse_price_db("P 2015-10-25 \"MUTF2351 $5.42");
+	assert_eq!(result.map_err(|failure| failure.message),
+		Err("Unterminated quoted symbol starting at line 1, column 14".to_string()));
+}
+
+#[test]
+fn quoted_symbol_unterminated_at_line_end() {
+	let result = parse_price_db("P 2015-10-24 AAPL $118.88\r\nP 2015-10-25 \"unterminated\r\n");
+	let failure = result.unwrap_err();
+	assert_eq!(failure.message, "Unterminated quoted symbol starting at line 2, column 14");
+}
+
+#[test]
+fn quoted_symbol_test() {
+	let result = parser(quoted_symbol)
+		.parse("\"MUTF2351\"")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Symbol {
+		value: "MUTF2351".to_string(),
+		quoted: true
+	}));
+}
+
+
+
+/// Parses an unquoted symbol
+fn unquoted_symbol<I>(input: State<I>) -> ParseResult<Symbol, I>
+where I: Stream<Item=char> {
+	many1(satisfy(|c| "-0123456789; \"\t\r\n\u{00A0}{}@=".chars().all(|s| s != c)))
+		.map(|symbol| Symbol {
+			value: symbol,
+			quoted: false
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn unquoted_symbol_just_symbol() {
+	let result = parser(unquoted_symbol)
+		.parse("$")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Symbol {
+		value: "$".to_string(),
+		quoted: false
+	}));
+}
+
+#[test]
+fn unquoted_symbol_symbol_and_letters() {
+	let result = parser(unquoted_symbol)
+		.parse("US$")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Symbol {
+		value: "US$".to_string(),
+		quoted: false
+	}));
+}
+
+#[test]
+fn unquoted_symbol_just_letters() {
+	let result = parser(unquoted_symbol)
+		.parse("AAPL")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Symbol {
+		value: "AAPL".to_string(),
+		quoted: false
+	}));
+}
+
+
+
+/// Parses a quoted or unquoted symbol
+fn symbol<I>(input: State<I>) -> ParseResult<Symbol, I>
+where I: Stream<Item=char> {
+	parser(quoted_symbol)
+		.or(parser(unquoted_symbol))
+		.parse_state(input)
+}
+
+#[test]
+fn symbol_unquoted_test() {
+	let result = parser(symbol)
+		.parse("$")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Symbol {
+		value: "$".to_string(),
+		quoted: false
+	}));
+}
+
+#[test]
+fn symbol_quoted_test() {
+	let result = parser(symbol)
+		.parse("\"MUTF2351\"")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Symbol {
+		value: "MUTF2351".to_string(),
+		quoted: true
+	}));
+}
+
+#[test]
+fn symbol_equality_ignores_quoting() {
+	let quoted = Symbol {
+		value: "AAPL".to_string(),
+		quoted: true
+	};
+	let unquoted = Symbol {
+		value: "AAPL".to_string(),
+		quoted: false
+	};
+	assert_eq!(quoted, unquoted);
+	assert!(quoted != Symbol {
+		value: "GOOG".to_string(),
+		quoted: true
+	});
+	assert_eq!(format!("{:?}", quoted), "Symbol { value: \"AAPL\", quoted: true }");
+}
+
+#[test]
+fn symbol_quoted_and_unquoted_amounts_are_same_commodity() {
+	let quoted = parser(amount).parse("10 \"AAPL\"").map(|x| x.0.symbol);
+	let unquoted = parser(amount).parse("10 AAPL").map(|x| x.0.symbol);
+	assert_eq!(quoted, unquoted);
+}
+
+
+
+/// Parses an amount in the format of symbol then quantity.
+/// The sign may also come before the symbol. e.g. -$5.00
+#[cfg(test)]
+fn amount_symbol_then_quantity<I>(input: State<I>) -> ParseResult<Amount, I>
+where I: Stream<Item=char> {
+	amount_symbol_then_quantity_in(NumberFormat::DECIMAL_POINT, input)
+}
+
+/// Parses an amount in the format of symbol then quantity, with the quantity
+/// in the given number format.
+fn amount_symbol_then_quantity_in<I>(format: NumberFormat, input: State<I>) -> ParseResult<Amount, I>
+where I: Stream<Item=char> {
+	(
+		try((optional(char('-')), parser(symbol))),
+		optional(parser(whitespace)),
+		parser(|input| quantity_in(format, input))
+	)
+		.map(|((opt_minus, symbol), opt_whitespace, quantity)| {
+			let format = match (opt_minus, opt_whitespace) {
+				(Some(_), Some(_)) => AmountFormat::MinusSymbolLeftWithSpace,
+				(Some(_), None) => AmountFormat::MinusSymbolLeftNoSpace,
+				(None, Some(_)) => AmountFormat::SymbolLeftWithSpace,
+				(None, None) => AmountFormat::SymbolLeftNoSpace
+			};
+			Amount {
+				value: if opt_minus.is_some() { -quantity } else { quantity },
+				symbol: symbol,
+				format: format
+			}
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn amount_symbol_then_quantity_no_whitespace() {
+	let result = parser(amount_symbol_then_quantity)
+		.parse("$13,245.00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Amount {
+		value: Decimal::new(1324500, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	}));
+}
+
+#[test]
+fn amount_symbol_then_quantity_with_whitespace() {
+	let result = parser(amount_symbol_then_quantity)
+		.parse("$ 13,245.00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Amount {
+		value: Decimal::new(1324500, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftWithSpace
+	}));
+}
+
+#[test]
+fn amount_symbol_then_quantity_with_non_breaking_space() {
+	let result = parser(amount_symbol_then_quantity)
+		.parse("$\u{00A0}5.00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Amount {
+		value: Decimal::new(500, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftWithSpace
+	}));
+}
+
+#[test]
+fn amount_symbol_then_quantity_minus_before_symbol() {
+	let result = parser(amount_symbol_then_quantity)
+		.parse("-$5.00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Amount {
+		value: Decimal::new(-500, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::MinusSymbolLeftNoSpace
+	}));
+}
+
+#[test]
+fn amount_symbol_then_quantity_minus_either_side_of_symbol() {
+	let before = parser(amount_symbol_then_quantity).parse("-$5.00").map(|x| x.0.value);
+	let after = parser(amount_symbol_then_quantity).parse("$-5.00").map(|x| x.0.value);
+	assert_eq!(before, after);
+}
+
+
+
+/// Parses an amount in the format of quantity then symbol.
+#[cfg(test)]
+fn amount_quantity_then_symbol<I>(input: State<I>) -> ParseResult<Amount, I>
+where I: Stream<Item=char> {
+	amount_quantity_then_symbol_in(NumberFormat::DECIMAL_POINT, input)
+}
+
+/// Parses an amount in the format of quantity then symbol, with the quantity
+/// in the given number format.
+fn amount_quantity_then_symbol_in<I>(format: NumberFormat, input: State<I>) -> ParseResult<Amount, I>
+where I: Stream<Item=char> {
+	(
+		parser(|input| quantity_in(format, input)),
+		optional(parser(whitespace)),
+		parser(symbol)
+	)
+		.map(|(quantity, opt_whitespace, symbol)| {
+			let format = match opt_whitespace {
+				Some(_) => AmountFormat::SymbolRightWithSpace,
+				None => AmountFormat::SymbolRightNoSpace
+			};
+			Amount {
+				value: quantity,
+				symbol: symbol,
+				format: format
+			}
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn amount_quantity_then_symbol_no_whitespace() {
+	let result = parser(amount_quantity_then_symbol)
+		.parse("13,245.463AAPL")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Amount {
+		value: Decimal::new(13245463, 3),
+		symbol: Symbol {
+			value: "AAPL".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolRightNoSpace
+	}));
+}
+
+#[test]
+fn amount_quantity_then_symbol_with_whitespace() {
+	let result = parser(amount_quantity_then_symbol)
+		.parse("13,245.463 \"MUTF2351\"")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Amount {
+		value: Decimal::new(13245463, 3),
+		symbol: Symbol {
+			value: "MUTF2351".to_string(),
+			quoted: true
+		},
+		format: AmountFormat::SymbolRightWithSpace
+	}));
+}
+
+
+
+/// Parses an amount
+#[cfg(test)]
+fn amount<I>(input: State<I>) -> ParseResult<Amount, I>
+where I: Stream<Item=char> {
+	amount_in(NumberFormat::DECIMAL_POINT, input)
+}
+
+/// Parses an amount with its quantity in the given number format.
+fn amount_in<I>(format: NumberFormat, input: State<I>) -> ParseResult<Amount, I>
+where I: Stream<Item=char> {
+	parser(|input| amount_symbol_then_quantity_in(format, input))
+		.or(parser(|input| amount_quantity_then_symbol_in(format, input)))
+		.parse_state(input)
+}
+
+#[test]
+fn amount_test_symbol_then_quantity() {
+	let result = parser(amount)
+		.parse("$13,245.46")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Amount {
+		value: Decimal::new(1324546, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	}));
+}
+
+#[test]
+fn amount_test_quantity_then_symbol() {
+	let result = parser(amount)
+		.parse("13,245.463 \"MUTF2351\"")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Amount {
+		value: Decimal::new(13245463, 3),
+		symbol: Symbol {
+			value: "MUTF2351".to_string(),
+			quoted: true
+		},
+		format: AmountFormat::SymbolRightWithSpace
+	}));
+}
+
+#[test]
+fn amount_test_negative_quantity_then_symbol() {
+	let result = parser(amount)
+		.parse("-5 AAPL")
+		.map(|x| x.0.value);
+	assert_eq!(result, Ok(Decimal::new(-5, 0)));
+}
+
+
+impl Amount {
+	/// Number of digits after the decimal point as written in the source.
+	pub fn precision(&self) -> u32 {
+		self.value.scale()
+	}
+
+	/// Rounds to the nearest multiple of `step`, e.g. the nearest 0.05 where
+	/// there are no one cent coins. Halfway values round away from zero. None
+	/// when `step` is zero or the result overflows.
+	pub fn round_to_denomination(&self, step: Decimal) -> Option<Amount> {
+		let mut value = self.value.checked_div(step)?
+			.round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero)
+			.checked_mul(step)?;
+		value.rescale(self.value.scale().max(step.scale()));
+		Some(Amount {
+			value: value,
+			symbol: self.symbol.clone(),
+			format: self.format
+		})
+	}
+}
+
+#[test]
+fn amount_precision_two_digits() {
+	let (amount, _) = parser(amount).parse("$5.40").unwrap();
+	assert_eq!(amount.precision(), 2);
+}
+
+#[test]
+fn amount_precision_one_digit() {
+	let (amount, _) = parser(amount).parse("$5.4").unwrap();
+	assert_eq!(amount.precision(), 1);
+}
+
+#[test]
+fn amount_precision_no_fractional_part() {
+	let (amount, _) = parser(amount).parse("$5").unwrap();
+	assert_eq!(amount.precision(), 0);
+}
+
+#[test]
+fn amount_round_to_denomination_down() {
+	let amount = Amount {
+		value: Decimal::new(102, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	};
+	assert_eq!(amount.round_to_denomination(Decimal::new(5, 2)), Some(Amount {
+		value: Decimal::new(100, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	}));
+}
+
+#[test]
+fn amount_round_to_denomination_up() {
+	let amount = Amount {
+		value: Decimal::new(103, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	};
+	assert_eq!(amount.round_to_denomination(Decimal::new(5, 2)), Some(Amount {
+		value: Decimal::new(105, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	}));
+}
+
+#[test]
+fn amount_round_to_denomination_zero_step() {
+	let amount = Amount {
+		value: Decimal::new(103, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	};
+	assert_eq!(amount.round_to_denomination(Decimal::ZERO), None);
+}
+
+
+impl Amount {
+	/// Applies `op` to the values of two amounts of the same commodity, keeping
+	/// this amount's format and the greater precision of the two.
+	fn combine<F>(&self, other: &Amount, op: F) -> Result<Amount, CommodityMismatch>
+	where F: Fn(Decimal, Decimal) -> Decimal {
+		if self.symbol != other.symbol {
+			return Err(CommodityMismatch {
+				left: self.symbol.clone(),
+				right: other.symbol.clone()
+			});
+		}
+		let mut value = op(self.value, other.value);
+		value.rescale(self.precision().max(other.precision()));
+		Ok(Amount {
+			value: value,
+			symbol: self.symbol.clone(),
+			format: self.format
+		})
+	}
+
+	/// Adds an amount of the same commodity.
+	pub fn add(&self, other: &Amount) -> Result<Amount, CommodityMismatch> {
+		self.combine(other, |a, b| a + b)
+	}
+
+	/// Subtracts an amount of the same commodity.
+	pub fn sub(&self, other: &Amount) -> Result<Amount, CommodityMismatch> {
+		self.combine(other, |a, b| a - b)
+	}
+
+	/// Subtracts an amount of any commodity. Unlike `sub`, a different
+	/// commodity is kept as a second, negated component rather than an error.
+	pub fn minus_mixed(&self, other: &Amount) -> MixedAmount {
+		let amounts = match self.sub(other) {
+			Ok(difference) => vec![difference],
+			Err(_) => vec![self.clone(), Amount {
+				value: -other.value,
+				symbol: other.symbol.clone(),
+				format: other.format
+			}]
+		};
+		MixedAmount {
+			amounts: amounts
+		}
+	}
+}
+
+#[test]
+fn amount_add() {
+	let (left, _) = parser(amount).parse("$5.40").unwrap();
+	let (right, _) = parser(amount).parse("$1.1").unwrap();
+	let result = left.add(&right).map(|sum| sum.to_string());
+	assert_eq!(result, Ok("$6.50".to_string()));
+}
+
+#[test]
+fn amount_sub_to_negative() {
+	let (left, _) = parser(amount).parse("$1.10").unwrap();
+	let (right, _) = parser(amount).parse("$5.40").unwrap();
+	let result = left.sub(&right).map(|difference| difference.to_string());
+	assert_eq!(result, Ok("$-4.30".to_string()));
+}
+
+#[test]
+fn amount_minus_mixed_different_commodities() {
+	let (left, _) = parser(amount).parse("$5").unwrap();
+	let (right, _) = parser(amount).parse("10 AAPL").unwrap();
+	let result: Vec<String> = left.minus_mixed(&right).amounts.iter()
+		.map(|amount| amount.to_string())
+		.collect();
+	assert_eq!(result, vec!["$5".to_string(), "-10 AAPL".to_string()]);
+}
+
+#[test]
+fn amount_minus_mixed_same_commodity() {
+	let (left, _) = parser(amount).parse("$5.40").unwrap();
+	let (right, _) = parser(amount).parse("$1.10").unwrap();
+	let result: Vec<String> = left.minus_mixed(&right).amounts.iter()
+		.map(|amount| amount.to_string())
+		.collect();
+	assert_eq!(result, vec!["$4.30".to_string()]);
+}
+
+/// Groups amounts by the value of their commodity symbol, so quoted and
+/// unquoted forms of a symbol share a group.
+pub fn group_by_commodity(amounts: &[Amount]) -> HashMap<String, Vec<&Amount>> {
+	let mut groups: HashMap<String, Vec<&Amount>> = HashMap::new();
+	for amount in amounts {
+		groups.entry(amount.symbol.value.clone()).or_default().push(amount);
+	}
+	groups
+}
+
+#[test]
+fn group_by_commodity_test() {
+	let amounts: Vec<Amount> = ["$5.00", "10 AAPL", "$-2.50", "3 \"AAPL\""].iter()
+		.map(|text| parser(amount).parse(*text).unwrap().0)
+		.collect();
+	let groups = group_by_commodity(&amounts);
+	assert_eq!(groups.len(), 2);
+	assert_eq!(groups["$"], vec![&amounts[0], &amounts[2]]);
+	assert_eq!(groups["AAPL"], vec![&amounts[1], &amounts[3]]);
+}
+
+#[test]
+fn amount_add_different_commodities_is_error() {
+	let (left, _) = parser(amount).parse("$5").unwrap();
+	let (right, _) = parser(amount).parse("10 AAPL").unwrap();
+	assert_eq!(left.add(&right), Err(CommodityMismatch {
+		left: left.symbol.clone(),
+		right: right.symbol.clone()
+	}));
+}
+
+
+
+impl Amount {
+	/// Converts to a whole number of minor units at the given scale. Returns
+	/// None if the amount has more precision than the scale can hold, or is
+	/// too large.
+	pub fn to_minor_units(&self, scale: u32) -> Option<MinorUnitAmount> {
+		if self.value.normalize().scale() > scale {
+			return None;
+		}
+		let mut value = self.value;
+		value.rescale(scale);
+		i64::try_from(value.mantissa()).ok().map(|units| MinorUnitAmount {
+			units: units,
+			scale: scale,
+			symbol: self.symbol.clone(),
+			format: self.format
+		})
+	}
+}
+
+impl MinorUnitAmount {
+	/// Converts back to a decimal amount with the minor unit's precision.
+	pub fn to_amount(&self) -> Amount {
+		Amount {
+			value: Decimal::new(self.units, self.scale),
+			symbol: self.symbol.clone(),
+			format: self.format
+		}
+	}
+}
+
+#[test]
+fn amount_to_minor_units() {
+	let (amount, _) = parser(amount).parse("$5.42").unwrap();
+	assert_eq!(amount.to_minor_units(2), Some(MinorUnitAmount {
+		units: 542,
+		scale: 2,
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	}));
+}
+
+#[test]
+fn amount_to_minor_units_too_precise() {
+	let (amount, _) = parser(amount).parse("$5.425").unwrap();
+	assert_eq!(amount.to_minor_units(2), None);
+}
+
+#[test]
+fn minor_units_to_amount() {
+	let minor = MinorUnitAmount {
+		units: 542,
+		scale: 2,
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	};
+	assert_eq!(minor.to_amount().to_string(), "$5.42");
+}
+
+impl Amount {
+	/// Formats just the signed value, with its digits grouped by commas and
+	/// the precision it was written with, but no commodity symbol. e.g.
+	/// $1,234.56 gives 1,234.56
+	pub fn format_value_only(&self) -> String {
+		self.format_value(NumberFormat::DECIMAL_POINT)
+	}
+
+	/// Formats just the signed value in the given number format, with the
+	/// precision it was written with but no commodity symbol. e.g. $1,234.56
+	/// gives 1.234,56 with a decimal comma.
+	pub fn format_value(&self, format: NumberFormat) -> String {
+		let (group_separator, decimal_separator) = format.separators();
+		let digits = self.value.abs().to_string();
+		let (whole, fraction) = match digits.find('.') {
+			Some(index) => (&digits[..index], &digits[index + 1..]),
+			None => (&digits[..], "")
+		};
+		let mut formatted = String::new();
+		if self.value.is_sign_negative() && !self.value.is_zero() {
+			formatted.push('-');
+		}
+		for (index, digit) in whole.chars().enumerate() {
+			if index > 0 && (whole.len() - index) % 3 == 0 {
+				formatted.push(group_separator);
+			}
+			formatted.push(digit);
+		}
+		if !fraction.is_empty() {
+			formatted.push(decimal_separator);
+		}
+		for (index, digit) in fraction.chars().enumerate() {
+			if format.fractional_grouping && index > 0 && index % 3 == 0 {
+				formatted.push(group_separator);
+			}
+			formatted.push(digit);
+		}
+		formatted
+	}
+}
+
+#[test]
+fn amount_format_value_only() {
+	let (amount, _) = parser(amount).parse("$1,234.56").unwrap();
+	assert_eq!(amount.format_value_only(), "1,234.56");
+}
+
+#[test]
+fn amount_format_value_fractional_grouping() {
+	let (amount, _) = parser(amount).parse("-1234.5678 BTC").unwrap();
+	let format = NumberFormat {
+		fractional_grouping: true,
+		..NumberFormat::DECIMAL_COMMA
+	};
+	assert_eq!(amount.format_value(format), "-1.234,567.8");
+	assert_eq!(amount.format_value(NumberFormat::DECIMAL_COMMA), "-1.234,5678");
+}
+
+#[test]
+fn amount_format_value_only_keeps_sign_and_precision() {
+	let (negative, _) = parser(amount).parse("-1234567.500 AAPL").unwrap();
+	assert_eq!(negative.format_value_only(), "-1,234,567.500");
+	let (whole, _) = parser(amount).parse("$100").unwrap();
+	assert_eq!(whole.format_value_only(), "100");
+}
+
+
+impl fmt::Display for Symbol {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if self.quoted {
+			write!(f, "\"{}\"", self.value)
+		} else {
+			write!(f, "{}", self.value)
+		}
+	}
+}
+
+impl fmt::Display for Amount {
+	/// Writes the amount back out in the format it was parsed from.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.format {
+			AmountFormat::SymbolLeftNoSpace => write!(f, "{}{}", self.symbol, self.value),
+			AmountFormat::SymbolLeftWithSpace => write!(f, "{} {}", self.symbol, self.value),
+			AmountFormat::SymbolRightNoSpace => write!(f, "{}{}", self.value, self.symbol),
+			AmountFormat::SymbolRightWithSpace => write!(f, "{} {}", self.value, self.symbol),
+			AmountFormat::MinusSymbolLeftNoSpace if self.value.is_sign_negative() =>
+				write!(f, "-{}{}", self.symbol, -self.value),
+			AmountFormat::MinusSymbolLeftNoSpace => write!(f, "{}{}", self.symbol, self.value),
+			AmountFormat::MinusSymbolLeftWithSpace if self.value.is_sign_negative() =>
+				write!(f, "-{} {}", self.symbol, -self.value),
+			AmountFormat::MinusSymbolLeftWithSpace => write!(f, "{} {}", self.symbol, self.value),
+			AmountFormat::TrailingMinusSymbolRightNoSpace if self.value.is_sign_negative() =>
+				write!(f, "{}-{}", -self.value, self.symbol),
+			AmountFormat::TrailingMinusSymbolRightNoSpace => write!(f, "{}{}", self.value, self.symbol),
+			AmountFormat::TrailingMinusSymbolRightWithSpace if self.value.is_sign_negative() =>
+				write!(f, "{}- {}", -self.value, self.symbol),
+			AmountFormat::TrailingMinusSymbolRightWithSpace => write!(f, "{} {}", self.value, self.symbol)
+		}
+	}
+}
+
+#[test]
+fn amount_display_symbol_left_no_space() {
+	let amount = Amount {
+		value: Decimal::new(1324546, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	};
+	assert_eq!(amount.to_string(), "$13245.46");
+}
+
+#[test]
+fn amount_display_symbol_left_with_space() {
+	let amount = Amount {
+		value: Decimal::new(-500, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftWithSpace
+	};
+	assert_eq!(amount.to_string(), "$ -5.00");
+}
+
+#[test]
+fn amount_display_symbol_right_no_space() {
+	let amount = Amount {
+		value: Decimal::new(10, 0),
+		symbol: Symbol {
+			value: "AAPL".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolRightNoSpace
+	};
+	assert_eq!(amount.to_string(), "10AAPL");
+}
+
+#[test]
+fn amount_display_symbol_right_with_space() {
+	let amount = Amount {
+		value: Decimal::new(1324546, 2),
+		symbol: Symbol {
+			value: "MUTF2351".to_string(),
+			quoted: true
+		},
+		format: AmountFormat::SymbolRightWithSpace
+	};
+	assert_eq!(amount.to_string(), "13245.46 \"MUTF2351\"");
+}
+
+#[test]
+fn amount_display_minus_before_symbol() {
+	let result = parser(amount)
+		.parse("-$ 5.00")
+		.map(|x| x.0.to_string());
+	assert_eq!(result, Ok("-$ 5.00".to_string()));
+}
+
+#[test]
+fn amount_display_trailing_minus() {
+	let (mut amount, _) = parser(amount).parse("-5.00 USD").unwrap();
+	assert_eq!(amount.to_string(), "-5.00 USD");
+	amount.format = AmountFormat::TrailingMinusSymbolRightWithSpace;
+	assert_eq!(amount.to_string(), "5.00- USD");
+	amount.format = AmountFormat::TrailingMinusSymbolRightNoSpace;
+	assert_eq!(amount.to_string(), "5.00-USD");
+	amount.value = -amount.value;
+	assert_eq!(amount.to_string(), "5.00USD");
+}
+
+#[test]
+fn amount_display_smallest_crypto_unit() {
+	let result = parser(amount)
+		.parse("0.00000001 BTC")
+		.map(|x| x.0);
+	let amount = result.unwrap();
+	assert_eq!(amount.value, Decimal::new(1, 8));
+	assert_eq!(amount.precision(), 8);
+	assert_eq!(amount.to_string(), "0.00000001 BTC");
+}
+
+#[test]
+fn amount_display_eight_decimals() {
+	let result = parser(amount)
+		.parse("1.23456789 ETH")
+		.map(|x| x.0);
+	let amount = result.unwrap();
+	assert_eq!(amount.value, Decimal::new(123456789, 8));
+	assert_eq!(amount.precision(), 8);
+	assert_eq!(amount.to_string(), "1.23456789 ETH");
+}
+
+#[test]
+fn amount_display_keeps_trailing_zeros_beyond_eight_decimals() {
+	let result = parser(amount)
+		.parse("0.0000000100 BTC")
+		.map(|x| x.0.to_string());
+	assert_eq!(result, Ok("0.0000000100 BTC".to_string()));
+}
+
+
+/// Parses an amount written in the given number format, or an inferred amount
+fn amount_or_inferred<I>(format: NumberFormat, input: State<I>)
+	-> ParseResult<(AmountSource, Option<Amount>), I>
+where I: Stream<Item=char> {
+	optional(parser(|input| amount_in(format, input)))
+		.map(|opt_amount| {
+			let source = match opt_amount {
+				Some(_) => AmountSource::Provided,
+				None => AmountSource::Inferred
+			};
+			(source, opt_amount)
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn amount_or_inferred_amount_provided() {
+	let result = parser(|input| amount_or_inferred(NumberFormat::DECIMAL_POINT, input))
+		.parse("$13,245.46")
+		.map(|x| x.0);
+	assert_eq!(result, Ok((AmountSource::Provided, Some(Amount {
+		value: Decimal::new(1324546, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	}))));
+}
+
+#[test]
+fn amount_or_inferred_no_amount() {
+	let result = parser(|input| amount_or_inferred(NumberFormat::DECIMAL_POINT, input))
+		.parse("")
+		.map(|x| x.0);
+	assert_eq!(result, Ok((AmountSource::Inferred, None)));
+}
+
+
+
+/// Parses a lot price annotation in the given number format. e.g. {$313.38}
+fn lot_price<I>(format: NumberFormat, input: State<I>) -> ParseResult<Amount, I>
+where I: Stream<Item=char> {
+	between(char('{'), char('}'), parser(|input| amount_in(format, input)))
+		.parse_state(input)
+}
+
+#[test]
+fn lot_price_test() {
+	let result = parser(|input| lot_price(NumberFormat::DECIMAL_POINT, input))
+		.parse("{$313.38}")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Amount {
+		value: Decimal::new(31338, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	}));
+}
+
+#[test]
+fn lot_price_quantity_then_symbol() {
+	let result = parser(|input| lot_price(NumberFormat::DECIMAL_POINT, input))
+		.parse("{313.38 USD}")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Amount {
+		value: Decimal::new(31338, 2),
+		symbol: Symbol {
+			value: "USD".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolRightWithSpace
+	}));
+}
+
+
+
+/// Parses a per-unit cost annotation in the given number format.
+/// e.g. @ $320.00
+fn cost<I>(format: NumberFormat, input: State<I>) -> ParseResult<Amount, I>
+where I: Stream<Item=char> {
+	(char('@'), optional(parser(whitespace)), parser(|input| amount_in(format, input)))
+		.map(|(_, _, amount)| amount)
+		.parse_state(input)
+}
+
+#[test]
+fn cost_test() {
+	let result = parser(|input| cost(NumberFormat::DECIMAL_POINT, input))
+		.parse("@ $320.00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Amount {
+		value: Decimal::new(32000, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	}));
+}
+
+
+
+/// Parses a balance assertion in the given number format. e.g. = $100.00
+fn balance_assertion<I>(format: NumberFormat, input: State<I>) -> ParseResult<Amount, I>
+where I: Stream<Item=char> {
+	(char('='), optional(parser(whitespace)), parser(|input| amount_in(format, input)))
+		.map(|(_, _, amount)| amount)
+		.parse_state(input)
+}
+
+#[test]
+fn balance_assertion_test() {
+	let result = parser(|input| balance_assertion(NumberFormat::DECIMAL_POINT, input))
+		.parse("= $100.00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Amount {
+		value: Decimal::new(10000, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	}));
+}
+
+
+
+/// Finds a posting date given in a posting comment. e.g. [2015-11-01] or
+/// [=2015-11-01]
+fn posting_date(comment: &str) -> Option<Date> {
+	let start = comment.find('[')?;
+	(char('['), optional(char('=')), parser(date), char(']'))
+		.map(|(_, _, date, _)| date)
+		.parse(&comment[start..])
+		.ok()
+		.map(|x| x.0)
+}
+
+#[test]
+fn posting_date_effective() {
+	assert_eq!(posting_date(" [=2015-11-01]"), Some(Date {
+		year: 2015,
+		month: 11,
+		day: 1,
+		separator: DateSeparator::Hyphen
+	}));
+}
+
+#[test]
+fn posting_date_absent() {
+	assert_eq!(posting_date(" groceries"), None);
+	assert_eq!(posting_date(" [not a date]"), None);
+}
+
+
+
+/// Parses the comment on a posting. With metadata continuation on, indented
+/// comment lines that follow are joined onto it with single spaces.
+fn posting_comment<I>(options: &ParserOptions, input: State<I>) -> ParseResult<String, I>
+where I: Stream<Item=char> {
+	if !options.metadata_continuation {
+		return parser(comment).parse_state(input);
+	}
+	(
+		parser(comment),
+		many::<Vec<String>, _>(
+			try(parser(line_ending).with(parser(whitespace)).with(parser(comment))))
+	)
+		.map(|(comment, continuations)| {
+			let mut comment = comment.trim_end().to_string();
+			for continuation in continuations {
+				comment.push(' ');
+				comment.push_str(continuation.trim());
+			}
+			comment
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn posting_comment_metadata_continuation() {
+	let text = "; note: a long value that\n        ;   wraps onto the next line";
+	let options = ParserOptions {
+		metadata_continuation: true,
+		..ParserOptions::default()
+	};
+	let result = parser(|input| posting_comment(&options, input))
+		.parse(text)
+		.map(|x| parse_comment_tags(&x.0));
+	assert_eq!(result.unwrap().value("note"), Some("a long value that wraps onto the next line"));
+
+	let result = parser(|input| posting_comment(&ParserOptions::default(), input))
+		.parse(text)
+		.map(|x| x.1);
+	assert_eq!(result, Ok("\n        ;   wraps onto the next line"));
+}
+
+#[test]
+fn transaction_metadata_continuation() {
+	let options = ParserOptions {
+		metadata_continuation: true,
+		..ParserOptions::default()
+	};
+	let result = parse_journal_with("\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $45.00  ; receipt: filed under\n\
+		\t  ; household, 2015\n\
+		\tAssets:Cash\n\
+	", &options);
+	match result.unwrap()[0] {
+		Entry::Transaction(ref transaction) => {
+			assert_eq!(transaction.postings.len(), 2);
+			assert_eq!(transaction.postings[0].meta().value("receipt"), Some("filed under household, 2015"));
+		},
+		ref entry => panic!("expected a transaction, got {:?}", entry)
+	}
+}
+
+
+
+/// Parses a transaction posting. Annotations must come in the canonical order
+/// the posting is displayed in: amount, {lot price}, @ cost, = balance
+/// assertion, then ;comment.
+fn posting<I>(options: &ParserOptions, input: State<I>) -> ParseResult<ParsedPosting, I>
+where I: Stream<Item=char> {
+	let format = options.number_format();
+	(
+		parser(line_number),
+		optional(parser(status).skip(optional(parser(whitespace)))),
+		parser(|input| posting_account(options, input)),
+		optional(parser(whitespace)).map(|gap| gap.map_or(0, |gap| gap.chars().count())),
+		parser(|input| amount_or_inferred(format, input)).skip(optional(parser(whitespace))),
+		optional(parser(|input| lot_price(format, input)).skip(optional(parser(whitespace)))),
+		optional(parser(|input| cost(format, input)).skip(optional(parser(whitespace)))),
+		optional(parser(|input| balance_assertion(format, input))
+			.skip(optional(parser(whitespace)))),
+		optional(parser(|input| posting_comment(options, input)))
+	)
+		.map(|(line_num, opt_status, (posting_type, account), gap, (amount_source, opt_amount),
+				opt_lot_price, opt_cost, opt_balance_assertion, opt_comment)| {
+			ParsedPosting {
+				line_number: line_num,
+				status: opt_status,
+				full_account: account.join(&options.account_separator.to_string()),
+				sub_accounts: account,
+				posting_type: posting_type,
+				gap: gap,
+				amount: opt_amount,
+				amount_source: amount_source,
+				lot_price: opt_lot_price,
+				cost: opt_cost,
+				balance_assertion: opt_balance_assertion,
+				date: opt_comment.as_ref().and_then(|comment| posting_date(comment)),
+				comment: opt_comment
+			}
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn posting_status() {
+	let statuses = [
+		("* Assets:Cash  $5.00", Some(TransactionStatus::Cleared)),
+		("! Assets:Cash  $5.00", Some(TransactionStatus::Uncleared)),
+		("Assets:Cash  $5.00", None)
+	];
+	for &(text, ref status) in statuses.iter() {
+		let result = parser(|input| posting(&ParserOptions::default(), input))
+			.parse(text)
+			.map(|x| (x.0.status, x.0.full_account, x.0.amount.map(|amount| amount.value)));
+		assert_eq!(result, Ok((status.clone(), "Assets:Cash".to_string(), Some(Decimal::new(500, 2)))));
+	}
+}
+
+#[test]
+fn posting_status_round_trip() {
+	let text = concat!(
+		"2015-10-20 Grocery Store\n",
+		"    * Expenses:Food                       $45.00\n",
+		"    ! Assets:Cash");
+	let result = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse(text)
+		.map(|x| x.0.to_string());
+	assert_eq!(result, Ok(text.to_string()));
+}
+
+#[test]
+fn posting_gap() {
+	let parse = |text: &str| parser(|input| posting(&ParserOptions::default(), input))
+		.parse(text)
+		.map(|x| x.0)
+		.unwrap();
+	assert_eq!(parse("Assets:Cash    $5.00").gap, 4);
+	assert_eq!(parse("Assets:Cash  $5.00").gap, 2);
+	assert_eq!(parse("Assets:Cash").gap, 0);
+	assert_eq!(parse("Assets:Cash    $5.00"), parse("Assets:Cash  $5.00"));
+}
+
+#[test]
+fn posting_account_with_spaces() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Assets:Savings:Emergency Fund  $45.00")
+		.map(|x| (x.0.full_account, x.0.amount.map(|amount| amount.value)));
+	assert_eq!(result, Ok((
+		"Assets:Savings:Emergency Fund".to_string(),
+		Some(Decimal::new(4500, 2))
+	)));
+}
+
+#[test]
+fn posting_account_only_at_end_of_input() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Equity:OpeningBalances")
+		.map(|x| (x.0.full_account, x.0.amount_source, x.0.amount, x.1));
+	assert_eq!(result, Ok((
+		"Equity:OpeningBalances".to_string(),
+		AmountSource::Inferred,
+		None,
+		""
+	)));
+}
+
+#[test]
+fn posting_account_only_followed_by_posting() {
+	let result = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("2015-01-01 Opening Balances\n    Equity:OpeningBalances\n    Assets:Cash  $100.00")
+		.map(|x| x.0.postings);
+	let postings = result.unwrap();
+	assert_eq!(postings.len(), 2);
+	assert_eq!(postings[0].full_account, "Equity:OpeningBalances");
+	assert_eq!(postings[0].amount, None);
+	assert_eq!(postings[1].full_account, "Assets:Cash");
+	assert!(postings[1].amount.is_some());
+}
+
+#[test]
+fn posting_with_all_components() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Assets:Savings\t$45.00\t;comment")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(ParsedPosting {
+		line_number: 1,
+		status: None,
+		full_account: "Assets:Savings".to_string(),
+		sub_accounts: vec![
+			"Assets".to_string(),
+			"Savings".to_string()
+		],
+		posting_type: PostingType::Real,
+		gap: 1,
+		amount: Some(Amount {
+			value: Decimal::new(4500, 2),
+			symbol: Symbol {
+				value: "$".to_string(),
+				quoted: false
+			},
+			format: AmountFormat::SymbolLeftNoSpace
+		}),
+		amount_source: AmountSource::Provided,
+		lot_price: None,
+		cost: None,
+		balance_assertion: None,
+		comment: Some("comment".to_string()),
+		date: None
+	}));
+}
+
+#[test]
+fn posting_with_all_components_commodity() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Assets:Investments\t13.508 \"MUTF2351\"\t;comment")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(ParsedPosting {
+		line_number: 1,
+		status: None,
+		full_account: "Assets:Investments".to_string(),
+		sub_accounts: vec![
+			"Assets".to_string(),
+			"Investments".to_string()
+		],
+		posting_type: PostingType::Real,
+		gap: 1,
+		amount: Some(Amount {
+			value: Decimal::new(13508, 3),
+			symbol: Symbol {
+				value: "MUTF2351".to_string(),
+				quoted: true
+			},
+			format: AmountFormat::SymbolRightWithSpace
+		}),
+		amount_source: AmountSource::Provided,
+		lot_price: None,
+		cost: None,
+		balance_assertion: None,
+		comment: Some("comment".to_string()),
+		date: None
+	}));
+}
+
+#[test]
+fn posting_with_amount_no_comment() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Assets:Savings\t$45.00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(ParsedPosting {
+		line_number: 1,
+		status: None,
+		full_account: "Assets:Savings".to_string(),
+		sub_accounts: vec![
+			"Assets".to_string(),
+			"Savings".to_string()
+		],
+		posting_type: PostingType::Real,
+		gap: 1,
+		amount: Some(Amount {
+			value: Decimal::new(4500, 2),
+			symbol: Symbol {
+				value: "$".to_string(),
+				quoted: false
+			},
+			format: AmountFormat::SymbolLeftNoSpace
+		}),
+		amount_source: AmountSource::Provided,
+		lot_price: None,
+		cost: None,
+		balance_assertion: None,
+		comment: None,
+		date: None
+	}));
+}
+
+#[test]
+fn posting_comment_directly_after_amount() {
+	let result = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("2015-10-20 * Grocery Store\n  Expenses:Food  $5;note")
+		.map(|x| x.0.postings);
+	assert_eq!(result, Ok(vec![ParsedPosting {
+		line_number: 2,
+		status: None,
+		full_account: "Expenses:Food".to_string(),
+		sub_accounts: vec![
+			"Expenses".to_string(),
+			"Food".to_string()
+		],
+		posting_type: PostingType::Real,
+		gap: 2,
+		amount: Some(Amount {
+			value: Decimal::new(5, 0),
+			symbol: Symbol {
+				value: "$".to_string(),
+				quoted: false
+			},
+			format: AmountFormat::SymbolLeftNoSpace
+		}),
+		amount_source: AmountSource::Provided,
+		lot_price: None,
+		cost: None,
+		balance_assertion: None,
+		comment: Some("note".to_string()),
+		date: None
+	}]));
+}
+
+#[test]
+fn posting_trailing_comment_after_two_spaces() {
+	let result = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("2015-10-20 * Grocery Store\n    Assets:Cash  $-5.00  ; groceries")
+		.map(|x| x.0.postings);
+	let postings = result.unwrap();
+	assert_eq!(postings[0].amount.as_ref().map(|a| a.to_string()), Some("$-5.00".to_string()));
+	assert_eq!(postings[0].comment, Some(" groceries".to_string()));
+}
+
+#[test]
+fn posting_without_trailing_comment() {
+	let result = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("2015-10-20 * Grocery Store\n    Assets:Cash  $-5.00\n")
+		.map(|x| x.0.postings);
+	let postings = result.unwrap();
+	assert_eq!(postings[0].amount.as_ref().map(|a| a.to_string()), Some("$-5.00".to_string()));
+	assert_eq!(postings[0].comment, None);
+}
+
+#[test]
+fn posting_with_bracketed_date() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Expenses:Food  $5.00  ; [=2015-11-01]")
+		.map(|x| x.0.date);
+	assert_eq!(result, Ok(Some(Date {
+		year: 2015,
+		month: 11,
+		day: 1,
+		separator: DateSeparator::Hyphen
+	})));
+}
+
+#[test]
+fn posting_inferred_amount_and_comment() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Assets:Savings\t;comment")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(ParsedPosting {
+		line_number: 1,
+		status: None,
+		full_account: "Assets:Savings".to_string(),
+		sub_accounts: vec![
+			"Assets".to_string(),
+			"Savings".to_string()
+		],
+		posting_type: PostingType::Real,
+		gap: 1,
+		amount: None,
+		amount_source: AmountSource::Inferred,
+		lot_price: None,
+		cost: None,
+		balance_assertion: None,
+		comment: Some("comment".to_string()),
+		date: None
+	}));
+}
+
+#[test]
+fn posting_inferred_amount_no_comment() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Assets:Savings")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(ParsedPosting {
+		line_number: 1,
+		status: None,
+		full_account: "Assets:Savings".to_string(),
+		sub_accounts: vec![
+			"Assets".to_string(),
+			"Savings".to_string()
+		],
+		posting_type: PostingType::Real,
+		gap: 0,
+		amount: None,
+		amount_source: AmountSource::Inferred,
+		lot_price: None,
+		cost: None,
+		balance_assertion: None,
+		comment: None,
+		date: None
+	}));
+}
+
+#[test]
+fn posting_virtual_account() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("(Assets:Cash)  $5.00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(ParsedPosting {
+		line_number: 1,
+		status: None,
+		full_account: "Assets:Cash".to_string(),
+		sub_accounts: vec![
+			"Assets".to_string(),
+			"Cash".to_string()
+		],
+		posting_type: PostingType::Virtual,
+		gap: 2,
+		amount: Some(Amount {
+			value: Decimal::new(500, 2),
+			symbol: Symbol {
+				value: "$".to_string(),
+				quoted: false
+			},
+			format: AmountFormat::SymbolLeftNoSpace
+		}),
+		amount_source: AmountSource::Provided,
+		lot_price: None,
+		cost: None,
+		balance_assertion: None,
+		comment: None,
+		date: None
+	}));
+}
+
+#[test]
+fn posting_with_lot_price() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Assets:Investments\t10 AAPL {$313.38}")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(ParsedPosting {
+		line_number: 1,
+		status: None,
+		full_account: "Assets:Investments".to_string(),
+		sub_accounts: vec![
+			"Assets".to_string(),
+			"Investments".to_string()
+		],
+		posting_type: PostingType::Real,
+		gap: 1,
+		amount: Some(Amount {
+			value: Decimal::new(10, 0),
+			symbol: Symbol {
+				value: "AAPL".to_string(),
+				quoted: false
+			},
+			format: AmountFormat::SymbolRightWithSpace
+		}),
+		amount_source: AmountSource::Provided,
+		lot_price: Some(Amount {
+			value: Decimal::new(31338, 2),
+			symbol: Symbol {
+				value: "$".to_string(),
+				quoted: false
+			},
+			format: AmountFormat::SymbolLeftNoSpace
+		}),
+		cost: None,
+		balance_assertion: None,
+		comment: None,
+		date: None
+	}));
+}
+
+#[test]
+fn posting_with_lot_price_and_cost() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Assets:Investments\t10 AAPL {$313.38} @ $320.00\t;comment")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(ParsedPosting {
+		line_number: 1,
+		status: None,
+		full_account: "Assets:Investments".to_string(),
+		sub_accounts: vec![
+			"Assets".to_string(),
+			"Investments".to_string()
+		],
+		posting_type: PostingType::Real,
+		gap: 1,
+		amount: Some(Amount {
+			value: Decimal::new(10, 0),
+			symbol: Symbol {
+				value: "AAPL".to_string(),
+				quoted: false
+			},
+			format: AmountFormat::SymbolRightWithSpace
+		}),
+		amount_source: AmountSource::Provided,
+		lot_price: Some(Amount {
+			value: Decimal::new(31338, 2),
+			symbol: Symbol {
+				value: "$".to_string(),
+				quoted: false
+			},
+			format: AmountFormat::SymbolLeftNoSpace
+		}),
+		cost: Some(Amount {
+			value: Decimal::new(32000, 2),
+			symbol: Symbol {
+				value: "$".to_string(),
+				quoted: false
+			},
+			format: AmountFormat::SymbolLeftNoSpace
+		}),
+		balance_assertion: None,
+		comment: Some("comment".to_string()),
+		date: None
+	}));
+}
+
+#[test]
+fn posting_with_amount_and_balance_assertion() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Assets:Cash  $-50.00 = $100.00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(ParsedPosting {
+		line_number: 1,
+		status: None,
+		full_account: "Assets:Cash".to_string(),
+		sub_accounts: vec![
+			"Assets".to_string(),
+			"Cash".to_string()
+		],
+		posting_type: PostingType::Real,
+		gap: 2,
+		amount: Some(Amount {
+			value: Decimal::new(-5000, 2),
+			symbol: Symbol {
+				value: "$".to_string(),
+				quoted: false
+			},
+			format: AmountFormat::SymbolLeftNoSpace
+		}),
+		amount_source: AmountSource::Provided,
+		lot_price: None,
+		cost: None,
+		balance_assertion: Some(Amount {
+			value: Decimal::new(10000, 2),
+			symbol: Symbol {
+				value: "$".to_string(),
+				quoted: false
+			},
+			format: AmountFormat::SymbolLeftNoSpace
+		}),
+		comment: None,
+		date: None
+	}));
+}
+
+#[test]
+fn posting_with_only_balance_assertion() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Assets:Cash  = $100.00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(ParsedPosting {
+		line_number: 1,
+		status: None,
+		full_account: "Assets:Cash".to_string(),
+		sub_accounts: vec![
+			"Assets".to_string(),
+			"Cash".to_string()
+		],
+		posting_type: PostingType::Real,
+		gap: 2,
+		amount: None,
+		amount_source: AmountSource::Inferred,
+		lot_price: None,
+		cost: None,
+		balance_assertion: Some(Amount {
+			value: Decimal::new(10000, 2),
+			symbol: Symbol {
+				value: "$".to_string(),
+				quoted: false
+			},
+			format: AmountFormat::SymbolLeftNoSpace
+		}),
+		comment: None,
+		date: None
+	}));
+}
+
+#[test]
+fn posting_with_lot_price_cost_and_balance_assertion() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Assets:Broker  10 AAPL {$300} @ $320 = 10 AAPL")
+		.map(|x| x.0);
+	let posting = result.unwrap();
+	let amount_text = |amount: &Option<Amount>| amount.as_ref().map(Amount::to_string);
+	assert_eq!(posting.full_account, "Assets:Broker");
+	assert_eq!(amount_text(&posting.amount), Some("10 AAPL".to_string()));
+	assert_eq!(amount_text(&posting.lot_price), Some("$300".to_string()));
+	assert_eq!(amount_text(&posting.cost), Some("$320".to_string()));
+	assert_eq!(amount_text(&posting.balance_assertion), Some("10 AAPL".to_string()));
+	assert_eq!(posting.comment, None);
+}
+
+#[test]
+fn transaction_with_assertion_only_posting() {
+	let entries = parse_journal("\
+		2015-10-31 * Balance check\n\
+		\tAssets:Cash  = $100.00\n\
+		\tExpenses:Food  $5.00\n\
+		\tAssets:Checking\n\
+	").unwrap();
+	match entries[0] {
+		Entry::Transaction(ref transaction) => {
+			let posting = &transaction.postings[0];
+			assert_eq!(posting.amount, None);
+			assert_eq!(posting.balance_assertion.as_ref().map(|amount| amount.value), Some(Decimal::new(10000, 2)));
+			assert_eq!(transaction.postings.len(), 3);
+		},
+		ref entry => panic!("expected a transaction, got {:?}", entry)
+	}
+}
+
+
+
+// Parses a comment line
+//fn comment_line<I>(input: State<I>) -> ParseResult<
+
+
+/// Parses a complete transaction: a header followed by its indented postings.
+#[cfg(test)]
+fn transaction<I>(options: &ParserOptions, input: State<I>) -> ParseResult<Transaction, I>
+where I: Stream<Item=char> {
+	transaction_in_year(options, None, input)
+}
+
+/// Parses a complete transaction, whose date may leave off the year when a
+/// default year is set.
+fn transaction_in_year<I>(options: &ParserOptions, default_year: Option<i32>, input: State<I>)
+	-> ParseResult<Transaction, I>
+where I: Stream<Item=char> {
+	(
+		parser(|input| header_in_year(options, default_year, input)),
+		many(
+			try((parser(line_ending), parser(whitespace)))
+				.with(parser(|input| posting(options, input)))
+		)
+	)
+		.map(|(header, postings)| Transaction {
+			header: header,
+			postings: postings,
+			applied_tags: Vec::new()
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn transaction_test() {
+	let result = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("2015-10-20 * Grocery Store\n    Expenses:Food  $45.00\n    Assets:Cash")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Transaction {
+		header: Header {
+			line_number: 1,
+			date: Date {
+				year: 2015,
+				month: 10,
+				day: 20,
+				separator: DateSeparator::Hyphen
+			},
+			effective_date: None,
+			status: TransactionStatus::Cleared,
+			code: None,
+			payee: "Grocery Store".to_string(),
+			comment: None
+		},
+		postings: vec![
+			ParsedPosting {
+				line_number: 2,
+				status: None,
+				full_account: "Expenses:Food".to_string(),
+				sub_accounts: vec![
+					"Expenses".to_string(),
+					"Food".to_string()
+				],
+				posting_type: PostingType::Real,
+				gap: 2,
+				amount: Some(Amount {
+					value: Decimal::new(4500, 2),
+					symbol: Symbol {
+						value: "$".to_string(),
+						quoted: false
+					},
+					format: AmountFormat::SymbolLeftNoSpace
+				}),
+				amount_source: AmountSource::Provided,
+				lot_price: None,
+				cost: None,
+				balance_assertion: None,
+				comment: None,
+				date: None
+			},
+			ParsedPosting {
+				line_number: 3,
+				status: None,
+				full_account: "Assets:Cash".to_string(),
+				sub_accounts: vec![
+					"Assets".to_string(),
+					"Cash".to_string()
+				],
+				posting_type: PostingType::Real,
+				gap: 0,
+				amount: None,
+				amount_source: AmountSource::Inferred,
+				lot_price: None,
+				cost: None,
+				balance_assertion: None,
+				comment: None,
+				date: None
+			}
+		],
+		applied_tags: vec![]
+	}));
+}
+
+#[test]
+fn transaction_stops_at_unindented_line() {
+	let result = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("2015-10-20 * Grocery Store\n    Assets:Cash\nP 2015-10-25 AAPL $5.42")
+		.map(|(transaction, rest)| (transaction.postings.len(), rest));
+	assert_eq!(result, Ok((1, "\nP 2015-10-25 AAPL $5.42")));
+}
+
+
+
+impl ParsedPosting {
+	/// Metadata from the posting's comment, e.g. ; category: groceries
+	pub fn meta(&self) -> CommentMeta {
+		self.comment.as_ref()
+			.map(|comment| parse_comment_tags(comment))
+			.unwrap_or_default()
+	}
+
+	/// The amount this posting contributes towards balancing its transaction:
+	/// the amount priced by its cost or lot price when it has one. Virtual
+	/// postings don't need to balance, so contribute nothing.
+	fn weight(&self) -> Result<Option<Amount>, BalanceError> {
+		let amount = match self.amount {
+			Some(ref amount) if self.posting_type != PostingType::Virtual => amount,
+			_ => return Ok(None)
+		};
+		match self.cost.as_ref().or(self.lot_price.as_ref()) {
+			Some(price) => amount.value.checked_mul(price.value)
+				.map(|value| Some(Amount {
+					value: value,
+					symbol: price.symbol.clone(),
+					format: price.format
+				}))
+				.ok_or(BalanceError::Overflow(self.line_number)),
+			None => Ok(Some(amount.clone()))
+		}
+	}
+}
+
+impl Transaction {
+	/// The date to order the transaction by: its effective date when
+	/// `effective` is set and it has one, otherwise its primary date.
+	pub fn sort_date(&self, effective: bool) -> &Date {
+		match self.header.effective_date {
+			Some(ref effective_date) if effective => effective_date,
+			_ => &self.header.date
+		}
+	}
+
+	/// Metadata from the header's comment followed by each posting's, in the
+	/// order they're written.
+	fn metas(&self) -> Vec<CommentMeta> {
+		let header = self.header.comment.as_ref()
+			.map(|comment| parse_comment_tags(comment))
+			.unwrap_or_default();
+		let mut metas = vec![header];
+		metas.extend(self.postings.iter().map(ParsedPosting::meta));
+		metas
+	}
+
+	/// The flag-style tags on the header and all postings.
+	pub fn tags(&self) -> BTreeSet<String> {
+		self.metas().into_iter()
+			.flat_map(|meta| meta.tags)
+			.collect()
+	}
+
+	/// The typed tags on the header and all postings. When a key appears more
+	/// than once, the value from the later posting wins.
+	pub fn value_tags(&self) -> BTreeMap<String, String> {
+		self.metas().into_iter()
+			.flat_map(|meta| meta.values)
+			.collect()
+	}
+
+	/// Postings without an amount. Virtual postings don't need to balance, so
+	/// are never counted.
+	fn missing_amounts(&self) -> usize {
+		self.postings.iter()
+			.filter(|posting| posting.posting_type != PostingType::Virtual
+				&& posting.amount.is_none())
+			.count()
+	}
+
+	/// Sum of the posting weights in each commodity, in order of first use.
+	fn commodity_totals(&self) -> Result<Vec<Amount>, BalanceError> {
+		let mut totals: Vec<Amount> = Vec::new();
+		for posting in &self.postings {
+			let weight = match posting.weight()? {
+				Some(weight) => weight,
+				None => continue
+			};
+			match totals.iter_mut().find(|total| total.symbol == weight.symbol) {
+				Some(total) => {
+					total.value = total.value.checked_add(weight.value)
+						.ok_or(BalanceError::Overflow(posting.line_number))?;
+				},
+				None => totals.push(weight)
+			}
+		}
+		Ok(totals)
+	}
+
+	/// Checks that the postings in each commodity sum to zero. A single posting
+	/// without an amount is inferred to balance the rest, as long as what's
+	/// left over is in one commodity; the same rule `balance` applies.
+	pub fn validate_balance(&self) -> Result<(), WealthError> {
+		let residual = self.residual().map_err(|error| match error {
+			BalanceError::Overflow(line) => WealthError::Overflow(line),
+			_ => WealthError::Unbalanced(self.header.line_number)
+		})?;
+		match (self.missing_amounts(), residual.len()) {
+			(0, 0) | (1, 0) | (1, 1) => Ok(()),
+			_ => Err(WealthError::Unbalanced(self.header.line_number))
+		}
+	}
+
+	/// Fills in the amount of the one posting without one, so that the postings
+	/// in each commodity sum to zero. When the others already sum to zero, it's
+	/// filled with zero of the first commodity they use.
+	pub fn balance(&mut self) -> Result<(), BalanceError> {
+		let line_number = self.header.line_number;
+		let mut residual = self.residual()?;
+		match (self.missing_amounts(), residual.len()) {
+			(0, 0) => Ok(()),
+			(0, _) => Err(BalanceError::Unbalanced(line_number)),
+			(1, 0) | (1, 1) => {
+				let inferred = match residual.pop() {
+					Some(residual) => Amount {
+						value: -residual.value,
+						..residual
+					},
+					None => match self.commodity_totals()?.into_iter().next() {
+						Some(total) => total,
+						None => return Ok(())
+					}
+				};
+				let posting = self.postings.iter_mut()
+					.find(|posting| posting.posting_type != PostingType::Virtual
+						&& posting.amount.is_none())
+					.expect("one posting is missing its amount");
+				posting.amount = Some(inferred);
+				Ok(())
+			},
+			(1, _) => Err(BalanceError::MultipleCommodities(line_number)),
+			_ => Err(BalanceError::TooManyMissingAmounts(line_number))
+		}
+	}
+
+	/// Whether the transaction balances. See `validate_balance`.
+	pub fn is_balanced(&self) -> bool {
+		self.validate_balance().is_ok()
+	}
+
+	/// The nonzero sums of the postings in each commodity, i.e. what is left
+	/// over when the transaction doesn't balance. Postings without an amount
+	/// are not inferred, so contribute nothing.
+	pub fn residual(&self) -> Result<Vec<Amount>, BalanceError> {
+		Ok(self.commodity_totals()?
+			.into_iter()
+			.filter(|total| !total.value.is_zero())
+			.collect())
+	}
+}
+
+#[test]
+fn transaction_tags() {
+	let entries = parse_journal("\
+		2015-10-20 * Grocery Store  ; :a:\n\
+		\tExpenses:Food  $45.00  ; project: X\n\
+		\tAssets:Cash  ; project: Y\n\
+	").unwrap();
+	match entries[0] {
+		Entry::Transaction(ref transaction) => {
+			assert_eq!(transaction.tags().into_iter().collect::<Vec<String>>(), vec!["a".to_string()]);
+			let value_tags = transaction.value_tags();
+			assert_eq!(value_tags.len(), 1);
+			assert_eq!(value_tags.get("project").map(String::as_str), Some("Y"));
+		},
+		ref entry => panic!("expected a transaction, got {:?}", entry)
+	}
+}
+
+#[test]
+fn transaction_is_balanced() {
+	let (transaction, _) = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("\
+			2015-10-20 * Grocery Store\n\
+			\tExpenses:Food  $45.00\n\
+			\tAssets:Cash  $-45.00\
+		")
+		.unwrap();
+	assert!(transaction.is_balanced());
+}
+
+#[test]
+fn transaction_is_balanced_with_inferred_amount() {
+	let (transaction, _) = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("\
+			2015-10-20 * Grocery Store\n\
+			\tExpenses:Food  $45.00\n\
+			\tAssets:Cash\
+		")
+		.unwrap();
+	assert!(transaction.is_balanced());
+}
+
+#[test]
+fn transaction_is_balanced_missing_amount_multiple_commodities() {
+	let (transaction, _) = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("\
+			2015-10-20 * Broker\n\
+			\tAssets:Investments  10 AAPL\n\
+			\tExpenses:Fees  $9.99\n\
+			\tAssets:Cash\
+		")
+		.unwrap();
+	assert!(!transaction.is_balanced());
+	assert_eq!(transaction.validate_balance(), Err(WealthError::Unbalanced(1)));
+}
+
+#[test]
+fn transaction_is_balanced_with_cost() {
+	let (transaction, _) = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("\
+			2015-10-20 * Broker\n\
+			\tAssets:Investments  10 AAPL @ $313.38\n\
+			\tAssets:Cash  $-3133.80\
+		")
+		.unwrap();
+	assert!(transaction.is_balanced());
+}
+
+#[test]
+fn transaction_is_not_balanced() {
+	let (transaction, _) = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("\
+			2015-10-20 * Grocery Store\n\
+			\tExpenses:Food  $45.00\n\
+			\tAssets:Cash  $-44.99\
+		")
+		.unwrap();
+	assert!(!transaction.is_balanced());
+	assert_eq!(transaction.validate_balance(), Err(WealthError::Unbalanced(1)));
+}
+
+#[test]
+fn transaction_is_balanced_overflow() {
+	let entries = parse_journal("\
+		2015-10-20 * Buy\n\
+		\tAssets:Broker  99999999999999999999 AAPL @ $99999999999999\n\
+		\tAssets:Cash\n\
+	").unwrap();
+	match entries[0] {
+		Entry::Transaction(ref transaction) => {
+			assert!(!transaction.is_balanced());
+			assert_eq!(transaction.validate_balance(), Err(WealthError::Overflow(2)));
+			assert_eq!(transaction.clone().balance(), Err(BalanceError::Overflow(2)));
+		},
+		ref entry => panic!("expected a transaction, got {:?}", entry)
+	}
+}
+
+#[test]
+fn transaction_is_balanced_sum_overflow() {
+	let entries = parse_journal("\
+		2015-10-20 * Transfer\n\
+		\tAssets:Checking  $70000000000000000000000000000\n\
+		\tAssets:Savings  $70000000000000000000000000000\n\
+		\tEquity:Opening\n\
+	").unwrap();
+	match entries[0] {
+		Entry::Transaction(ref transaction) => {
+			assert!(!transaction.is_balanced());
+			assert_eq!(transaction.validate_balance(), Err(WealthError::Overflow(3)));
+		},
+		ref entry => panic!("expected a transaction, got {:?}", entry)
+	}
+}
+
+#[test]
+fn transaction_residual_balanced() {
+	let (transaction, _) = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("\
+			2015-10-20 * Grocery Store\n\
+			\tExpenses:Food  $45.00\n\
+			\tAssets:Cash  $-45.00\
+		")
+		.unwrap();
+	assert_eq!(transaction.residual(), Ok(vec![]));
+}
+
+#[test]
+fn transaction_residual_unbalanced() {
+	let (transaction, _) = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("\
+			2015-10-20 * Grocery Store\n\
+			\tExpenses:Food  $45.00\n\
+			\tAssets:Cash  $-44.99\
+		")
+		.unwrap();
+	let residual: Vec<String> = transaction.residual().unwrap().iter().map(|amount| amount.to_string()).collect();
+	assert_eq!(residual, vec!["$0.01".to_string()]);
+}
+
+#[test]
+fn transaction_balance_infers_amount() {
+	let (mut transaction, _) = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("\
+			2015-10-20 * Grocery Store\n\
+			\tExpenses:Food  $45.00\n\
+			\tAssets:Cash\
+		")
+		.unwrap();
+	assert_eq!(transaction.balance(), Ok(()));
+	assert_eq!(transaction.postings[1].amount, Some(Amount {
+		value: Decimal::new(-4500, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	}));
+	assert_eq!(transaction.postings[1].amount_source, AmountSource::Inferred);
+}
+
+#[test]
+fn transaction_balance_infers_zero_amount() {
+	let (mut transaction, _) = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("\
+			2015-10-20 * Grocery Store\n\
+			\tExpenses:Food  $45.00\n\
+			\tAssets:Cash  $-45.00\n\
+			\tEquity:Rounding\
+		")
+		.unwrap();
+	assert_eq!(transaction.balance(), Ok(()));
+	assert_eq!(transaction.postings[2].amount.as_ref().map(|amount| amount.to_string()),
+		Some("$0.00".to_string()));
+}
+
+#[test]
+fn transaction_balance_two_missing_amounts_is_error() {
+	let (mut transaction, _) = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("\
+			2015-10-20 * Grocery Store\n\
+			\tExpenses:Food  $45.00\n\
+			\tAssets:Cash\n\
+			\tAssets:Savings\
+		")
+		.unwrap();
+	assert_eq!(transaction.balance(), Err(BalanceError::TooManyMissingAmounts(1)));
+}
+
+#[test]
+fn transaction_balance_unbalanced_is_error() {
+	let (mut transaction, _) = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("\
+			2015-10-20 * Grocery Store\n\
+			\tExpenses:Food  $45.00\n\
+			\tAssets:Cash  $-44.99\
+		")
+		.unwrap();
+	assert_eq!(transaction.balance(), Err(BalanceError::Unbalanced(1)));
+}
+
+#[test]
+fn transaction_balance_multiple_commodities_is_error() {
+	let (mut transaction, _) = parser(|input| transaction(&ParserOptions::default(), input))
+		.parse("\
+			2015-10-20 * Broker\n\
+			\tAssets:Investments  10 AAPL\n\
+			\tExpenses:Fees  $9.99\n\
+			\tAssets:Cash\
+		")
+		.unwrap();
+	assert_eq!(transaction.balance(), Err(BalanceError::MultipleCommodities(1)));
+}
+
+
+
+/// Column that posting amounts are right-aligned to when displayed.
+const AMOUNT_COLUMN: usize = 48;
+
+impl fmt::Display for ParsedPosting {
+	/// Writes the posting back out as an indented ledger posting line, with
+	/// its amount right-aligned and annotations in the canonical order that
+	/// `posting` parses: {lot price}, @ cost, then = balance assertion.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let status = match self.status {
+			Some(TransactionStatus::Cleared) => "* ",
+			Some(TransactionStatus::Uncleared) => "! ",
+			Some(TransactionStatus::Unmarked) | None => ""
+		};
+		let account = match self.posting_type {
+			PostingType::Real => format!("{}{}", status, self.full_account),
+			PostingType::Virtual => format!("{}({})", status, self.full_account),
+			PostingType::BalancedVirtual => format!("{}[{}]", status, self.full_account)
+		};
+		write!(f, "    {}", account)?;
+		if let Some(ref amount) = self.amount {
+			let amount = amount.to_string();
+			let used = 4 + account.chars().count() + amount.chars().count();
+			let gap = AMOUNT_COLUMN.saturating_sub(used).max(2);
+			write!(f, "{:gap$}{}", "", amount, gap = gap)?;
+		}
+		if let Some(ref lot_price) = self.lot_price {
+			write!(f, " {{{}}}", lot_price)?;
+		}
+		if let Some(ref cost) = self.cost {
+			write!(f, " @ {}", cost)?;
+		}
+		if let Some(ref balance_assertion) = self.balance_assertion {
+			let gap = if self.amount.is_some() { " " } else { "  " };
+			write!(f, "{}= {}", gap, balance_assertion)?;
+		}
+		if let Some(ref comment) = self.comment {
+			write!(f, "  ;{}", comment)?;
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Display for Transaction {
+	/// Writes the transaction back out as ledger text: the header line followed
+	/// by one line per posting.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.header)?;
+		for posting in &self.postings {
+			write!(f, "\n{}", posting)?;
+		}
+		Ok(())
+	}
+}
+
+#[test]
+fn posting_meta() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Expenses:Food  $5.00  ; category: groceries")
+		.map(|x| x.0.meta());
+	let meta = result.unwrap();
+	assert_eq!(meta.value("category"), Some("groceries"));
+	assert_eq!(meta.value("trip"), None);
+}
+
+#[test]
+fn posting_meta_without_comment() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Expenses:Food  $5.00")
+		.map(|x| x.0.meta());
+	assert_eq!(result, Ok(CommentMeta::default()));
+}
+
+#[test]
+fn posting_display() {
+	let result = parser(|input| posting(&ParserOptions::default(), input))
+		.parse("Assets:Investments  10 AAPL @ $313.38 ;note")
+		.map(|x| x.0.to_string());
+	assert_eq!(result, Ok("    Assets:Investments                   10 AAPL @ $313.38  ;note".to_string()));
+}
+
+#[test]
+fn posting_display_round_trip_all_annotations() {
+	let parse = |text: &str| parser(|input| posting(&ParserOptions::default(), input))
+		.parse(text)
+		.map(|x| x.0)
+		.unwrap();
+	let posting = parse("Assets:Investments  10 AAPL {$313.38} @ $320.00 = 30 AAPL ;sold");
+	let text = posting.to_string();
+	assert_eq!(text, "    Assets:Investments                   10 AAPL {$313.38} @ $320.00 = 30 AAPL  ;sold");
+	assert_eq!(parse(text.trim_start()), posting);
+}
+
+#[test]
+fn posting_annotations_out_of_order_is_error() {
+	let result = parse_journal("\
+		2015-10-20 * Broker\n\
+		\tAssets:Investments  10 AAPL @ $320.00 {$313.38}\n\
+		\tAssets:Cash\n\
+	");
+	assert!(result.is_err());
+}
+
+#[test]
+fn transaction_display_round_trip_with_cost() {
+	let parse = |text: &str| parser(|input| transaction(&ParserOptions::default(), input))
+		.parse(text)
+		.map(|x| x.0)
+		.unwrap();
+	let transaction = parse("\
+		2015-10-20 * Broker\n\
+		\tAssets:Investments  10 AAPL @ $313.38\n\
+		\tAssets:Cash  $-3133.80\
+	");
+	let text = transaction.to_string();
+	assert_eq!(text, "\
+		2015-10-20 * Broker\n    \
+		Assets:Investments                   10 AAPL @ $313.38\n    \
+		Assets:Cash                        $-3133.80\
+	");
+	assert_eq!(parse(&text), transaction);
+}
+
+#[test]
+fn transaction_display_round_trip() {
+	let parse = |text: &str| parser(|input| transaction(&ParserOptions::default(), input))
+		.parse(text)
+		.map(|x| x.0)
+		.unwrap();
+	let transaction = parse("\
+		2015-10-20 ! (#1042) Grocery Store ;weekly shop\n\
+		\tExpenses:Food  $45.00 ; :groceries:\n\
+		\t(Budget:Food)  $-45.00\n\
+		\tAssets:Cash\
+	");
+	let text = transaction.to_string();
+	assert_eq!(text, "\
+		2015-10-20 ! (#1042) Grocery Store ;weekly shop\n    \
+		Expenses:Food                         $45.00  ; :groceries:\n    \
+		(Budget:Food)                        $-45.00\n    \
+		Assets:Cash\
+	");
+	assert_eq!(parse(&text), transaction);
+}
+
+
+
+impl StatusFilter {
+	/// Whether a transaction with the given status passes the filter.
+	pub fn matches(&self, status: &TransactionStatus) -> bool {
+		match *status {
+			TransactionStatus::Cleared => self.cleared,
+			TransactionStatus::Uncleared => self.uncleared,
+			TransactionStatus::Unmarked => self.unmarked
+		}
+	}
+}
+
+#[test]
+fn status_filter_default_includes_all() {
+	let filter = StatusFilter::default();
+	assert!(filter.matches(&TransactionStatus::Cleared));
+	assert!(filter.matches(&TransactionStatus::Uncleared));
+	assert!(filter.matches(&TransactionStatus::Unmarked));
+}
+
+#[test]
+fn status_filter_cleared_only() {
+	let filter = StatusFilter {
+		cleared: true,
+		uncleared: false,
+		unmarked: false
+	};
+	let statuses = [
+		TransactionStatus::Cleared,
+		TransactionStatus::Uncleared,
+		TransactionStatus::Unmarked
+	];
+	let result: Vec<&TransactionStatus> = statuses.iter()
+		.filter(|status| filter.matches(status))
+		.collect();
+	assert_eq!(result, vec![&TransactionStatus::Cleared]);
+}
+
+#[test]
+fn status_filter_pending_and_unmarked() {
+	let filter = StatusFilter {
+		cleared: false,
+		uncleared: true,
+		unmarked: true
+	};
+	let statuses = [
+		TransactionStatus::Cleared,
+		TransactionStatus::Uncleared,
+		TransactionStatus::Unmarked
+	];
+	let result: Vec<&TransactionStatus> = statuses.iter()
+		.filter(|status| filter.matches(status))
+		.collect();
+	assert_eq!(result, vec![&TransactionStatus::Uncleared, &TransactionStatus::Unmarked]);
+}
+
+
+
+/// Parses an account directive and its indented note and alias lines.
+/// e.g. account Assets:Checking followed by "    alias checking"
+fn account_directive<I>(options: &ParserOptions, input: State<I>)
+	-> ParseResult<AccountDirective, I>
+where I: Stream<Item=char> {
+	let sub_directive = try((
+		parser(line_ending),
+		parser(whitespace),
+		string("note").or(string("alias"))
+	))
+		.skip(parser(whitespace))
+		.and(many1::<String, _>(satisfy(|c| c != '\r' && c != '\n')));
+
+	(
+		try(string("account").skip(parser(whitespace))),
+		parser(|input| account(options, input)),
+		many::<Vec<_>, _>(sub_directive)
+	)
+		.map(|(_, account, sub_directives)| {
+			let mut directive = AccountDirective {
+				full_account: account.join(&options.account_separator.to_string()),
+				sub_accounts: account,
+				note: None,
+				aliases: Vec::new()
+			};
+			for ((_, _, keyword), value) in sub_directives {
+				match keyword {
+					"note" => directive.note = Some(value),
+					_ => directive.aliases.push(value)
+				}
+			}
+			directive
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn account_directive_bare() {
+	let result = parser(|input| account_directive(&ParserOptions::default(), input))
+		.parse("account Assets:Checking")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(AccountDirective {
+		full_account: "Assets:Checking".to_string(),
+		sub_accounts: vec![
+			"Assets".to_string(),
+			"Checking".to_string()
+		],
+		note: None,
+		aliases: vec![]
+	}));
+}
+
+#[test]
+fn journal_account_directive() {
+	let entries = parse_journal("\
+		account Assets:Cash\n\
+		apply tag vacation\n\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Cash\n\
+	").unwrap();
+	assert_eq!(entries.len(), 3);
+	match entries[0] {
+		Entry::Account(ref account) => assert_eq!(account.full_account, "Assets:Cash"),
+		ref entry => panic!("expected an account directive, got {:?}", entry)
+	}
+	assert_eq!(entries[0].to_string(), "account Assets:Cash");
+}
+
+#[test]
+fn account_directive_with_note_and_aliases() {
+	let result = parser(|input| account_directive(&ParserOptions::default(), input))
+		.parse("account Assets:Checking\n    note Main chequing account\n    alias checking\n    alias chk")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(AccountDirective {
+		full_account: "Assets:Checking".to_string(),
+		sub_accounts: vec![
+			"Assets".to_string(),
+			"Checking".to_string()
+		],
+		note: Some("Main chequing account".to_string()),
+		aliases: vec![
+			"checking".to_string(),
+			"chk".to_string()
+		]
+	}));
+}
+
+
+
+/// Parses a commodity directive and its optional indented format line.
+/// e.g. commodity $ followed by "    format $1,000.00"
+fn commodity_directive<I>(input: State<I>) -> ParseResult<CommodityDirective, I>
+where I: Stream<Item=char> {
+	(
+		string("commodity").skip(parser(whitespace)),
+		parser(symbol),
+		optional(
+			try((parser(line_ending), parser(whitespace), string("format")))
+				.with(parser(whitespace))
+				.with(many1(satisfy(|c| c != '\r' && c != '\n')))
+		)
+	)
+		.map(|(_, symbol, format)| CommodityDirective {
+			symbol: symbol,
+			format: format
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn commodity_directive_single_line() {
+	let result = parser(commodity_directive)
+		.parse("commodity CAD")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(CommodityDirective {
+		symbol: Symbol {
+			value: "CAD".to_string(),
+			quoted: false
+		},
+		format: None
+	}));
+}
+
+#[test]
+fn commodity_directive_with_format() {
+	let result = parser(commodity_directive)
+		.parse("commodity $\n    format $1,000.00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(CommodityDirective {
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: Some("$1,000.00".to_string())
+	}));
+}
+
+#[test]
+fn commodity_directive_leaves_following_line() {
+	let result = parser(commodity_directive)
+		.parse("commodity CAD\nP 2015-10-25 AAPL $5.42");
+	assert_eq!(result, Ok((CommodityDirective {
+		symbol: Symbol {
+			value: "CAD".to_string(),
+			quoted: false
+		},
+		format: None
+	}, "\nP 2015-10-25 AAPL $5.42")));
+}
+
+
+
+/// Parses an include directive, returning the referenced path.
+/// e.g. include path/to/file.ledger
+fn include_directive<I>(input: State<I>) -> ParseResult<String, I>
+where I: Stream<Item=char> {
+	(
+		string("include").skip(parser(whitespace)),
+		many1::<String, _>(satisfy(|c| c != '\r' && c != '\n'))
+	)
+		.map(|(_, path)| path.trim_end().to_string())
+		.parse_state(input)
+}
+
+#[test]
+fn include_directive_relative_path() {
+	let result = parser(include_directive)
+		.parse("include accounts/2015.ledger")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("accounts/2015.ledger".to_string()));
+}
+
+#[test]
+fn include_directive_path_with_space() {
+	let result = parser(include_directive)
+		.parse("include My Documents/2015.ledger  ")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("My Documents/2015.ledger".to_string()));
+}
+
+
+
+/// Parses a default commodity directive, from which the commodity's symbol and
+/// display format are taken. e.g. D $1,000.00
+/// The amount may use either a decimal point or a decimal comma.
+fn default_commodity_directive<I>(input: State<I>) -> ParseResult<Amount, I>
+where I: Stream<Item=char> {
+	(
+		char('D'),
+		parser(whitespace),
+		try(parser(|input| amount_in(NumberFormat::DECIMAL_POINT, input)))
+			.or(parser(|input| amount_in(NumberFormat::DECIMAL_COMMA, input)))
+	)
+		.map(|(_, _, amount)| amount)
+		.parse_state(input)
+}
+
+#[test]
+fn default_commodity_directive_decimal_point() {
+	let result = parser(default_commodity_directive)
+		.parse("D $1,000.00")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Amount {
+		value: Decimal::new(100000, 2),
+		symbol: Symbol {
+			value: "$".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolLeftNoSpace
+	}));
+}
+
+#[test]
+fn default_commodity_directive_decimal_comma() {
+	let result = parser(default_commodity_directive)
+		.parse("D 1.000,00 CAD")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Amount {
+		value: Decimal::new(100000, 2),
+		symbol: Symbol {
+			value: "CAD".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolRightWithSpace
+	}));
+}
+
+
+
+/// Parses a year directive, setting the year for dates written without one.
+/// e.g. Y 2015
+fn year_directive<I>(input: State<I>) -> ParseResult<i32, I>
+where I: Stream<Item=char> {
+	(char('Y'), parser(whitespace), many1::<String, _>(digit()))
+		.and_then(|(_, _, year)| year.parse::<i32>())
+		.parse_state(input)
+}
+
+#[test]
+fn year_directive_test() {
+	let result = parser(year_directive)
+		.parse("Y 2015")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(2015));
+}
+
+
+
+/// Parses the start of an apply tag block, returning the tag.
+/// e.g. apply tag vacation
+fn apply_tag_directive<I>(input: State<I>) -> ParseResult<String, I>
+where I: Stream<Item=char> {
+	(
+		string("apply").skip(parser(whitespace)),
+		string("tag").skip(parser(whitespace)),
+		many1::<String, _>(satisfy(|c| c != '\r' && c != '\n'))
+	)
+		.map(|(_, _, tag)| tag.trim().to_string())
+		.parse_state(input)
+}
+
+#[test]
+fn apply_tag_directive_test() {
+	let result = parser(apply_tag_directive)
+		.parse("apply tag vacation ")
+		.map(|x| x.0);
+	assert_eq!(result, Ok("vacation".to_string()));
+}
+
+
+
+/// Parses the end of an apply tag block. e.g. end apply tag
+fn end_apply_tag_directive<I>(input: State<I>) -> ParseResult<(), I>
+where I: Stream<Item=char> {
+	(string("end"), parser(whitespace), string("apply"), parser(whitespace), string("tag"))
+		.map(|_| ())
+		.parse_state(input)
+}
+
+#[test]
+fn end_apply_tag_directive_test() {
+	let result = parser(end_apply_tag_directive)
+		.parse("end apply tag")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(()));
+}
+
+
+
+/// Parses a period expression, such as follows the ~ of a periodic
+/// transaction. e.g. monthly, every 2 weeks
+fn period<I>(input: State<I>) -> ParseResult<Period, I>
+where I: Stream<Item=char> {
+	many1(satisfy(|c| c != '\r' && c != '\n'))
+		.map(|text: String| interpret_period(text.trim()))
+		.parse_state(input)
+}
+
+fn interpret_period(text: &str) -> Period {
+	let lower = text.to_lowercase();
+	let words: Vec<&str> = lower.split_whitespace().collect();
+	let unit = |word: &str, interval: u32| match word.trim_end_matches('s') {
+		"day" => Some(Period::Daily(interval)),
+		"week" => Some(Period::Weekly(interval)),
+		"month" => Some(Period::Monthly(interval)),
+		"quarter" => Some(Period::Monthly(3 * interval)),
+		"year" => Some(Period::Yearly(interval)),
+		_ => None
+	};
+	let period = match words[..] {
+		["daily"] => Some(Period::Daily(1)),
+		["weekly"] => Some(Period::Weekly(1)),
+		["biweekly"] => Some(Period::Weekly(2)),
+		["monthly"] => Some(Period::Monthly(1)),
+		["bimonthly"] => Some(Period::Monthly(2)),
+		["quarterly"] => Some(Period::Monthly(3)),
+		["yearly"] | ["annually"] => Some(Period::Yearly(1)),
+		["every", word] => unit(word, 1),
+		["every", count, word] => count.parse().ok()
+			.filter(|&interval| interval > 0)
+			.and_then(|interval| unit(word, interval)),
+		_ => None
+	};
+	period.unwrap_or_else(|| Period::Other(text.to_string()))
+}
+
+#[test]
+fn period_monthly() {
+	let result = parser(period)
+		.parse("monthly")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Period::Monthly(1)));
+}
+
+#[test]
+fn period_every_two_weeks() {
+	let result = parser(period)
+		.parse("every 2 weeks")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Period::Weekly(2)));
+}
+
+#[test]
+fn period_yearly_and_every_year() {
+	let yearly = parser(period).parse("yearly").map(|x| x.0);
+	let every_year = parser(period).parse("every year").map(|x| x.0);
+	assert_eq!(yearly, Ok(Period::Yearly(1)));
+	assert_eq!(every_year, Ok(Period::Yearly(1)));
+}
+
+#[test]
+fn period_unknown_expression_kept() {
+	let result = parser(period)
+		.parse("every other Tuesday\n")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Period::Other("every other Tuesday".to_string())));
+}
+
+/// Parses a period expression on its own, e.g. from a report option. The
+/// whole input must be consumed, apart from a final line ending.
+pub fn parse_period(input: &str) -> Result<Period, ParseFailure> {
+	parser(period)
+		.skip(optional(parser(line_ending)))
+		.skip(not_followed_by(any()))
+		.parse(input)
+		.map(|x| x.0)
+		.map_err(ParseFailure::from)
+}
+
+#[test]
+fn parse_period_test() {
+	assert_eq!(parse_period("every 3 months\n"), Ok(Period::Monthly(3)));
+	assert_eq!(parse_period("Quarterly"), Ok(Period::Monthly(3)));
+	assert!(parse_period("").is_err());
+	assert!(parse_period("monthly\nweekly").is_err());
+}
+
+impl fmt::Display for Period {
+	/// Writes the period as an expression `period` parses back to the same
+	/// period. e.g. monthly, every 2 weeks
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Period::Daily(1) => write!(f, "daily"),
+			Period::Weekly(1) => write!(f, "weekly"),
+			Period::Monthly(1) => write!(f, "monthly"),
+			Period::Yearly(1) => write!(f, "yearly"),
+			Period::Daily(interval) => write!(f, "every {} days", interval),
+			Period::Weekly(interval) => write!(f, "every {} weeks", interval),
+			Period::Monthly(interval) => write!(f, "every {} months", interval),
+			Period::Yearly(interval) => write!(f, "every {} years", interval),
+			Period::Other(ref text) => write!(f, "{}", text)
+		}
+	}
+}
+
+#[test]
+fn period_display_round_trip() {
+	for text in &["daily", "weekly", "every 2 weeks", "monthly", "every 3 months", "yearly"] {
+		let period = parse_period(text).unwrap();
+		assert_eq!(period.to_string(), *text);
+	}
+}
+
+
+
+/// Parses a periodic transaction: a ~, a period expression, then postings.
+/// e.g. ~ monthly
+fn periodic_transaction<I>(options: &ParserOptions, input: State<I>) -> ParseResult<PeriodicTransaction, I>
+where I: Stream<Item=char> {
+	(
+		char('~').skip(parser(whitespace)),
+		parser(period),
+		many(
+			try((parser(line_ending), parser(whitespace)))
+				.with(parser(|input| posting(options, input)))
+		)
+	)
+		.map(|(_, period, postings)| PeriodicTransaction {
+			period: period,
+			postings: postings
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn periodic_transaction_test() {
+	let result = parser(|input| periodic_transaction(&ParserOptions::default(), input))
+		.parse("~ monthly\n\tExpenses:Food  $400.00\n\tAssets:Checking")
+		.map(|x| x.0);
+	let periodic = result.unwrap();
+	assert_eq!(periodic.period, Period::Monthly(1));
+	let accounts: Vec<&str> = periodic.postings.iter()
+		.map(|posting| posting.full_account.as_str())
+		.collect();
+	assert_eq!(accounts, vec!["Expenses:Food", "Assets:Checking"]);
+}
+
+
+
+/// Parses a price entry
+fn price<I>(input: State<I>) -> ParseResult<Price, I>
+where I: Stream<Item=char> {
+	price_with(&ParserOptions::default(), input)
+}
+
+/// Parses a price entry, with its date and amount written as the options
+/// allow, keeping the whitespace between its parts when the options say to
+/// preserve spacing.
+fn price_with<I>(options: &ParserOptions, input: State<I>) -> ParseResult<Price, I>
+where I: Stream<Item=char> {
+	let format = options.number_format();
+	(
+		char('P'),
+		parser(whitespace),
+		parser(|input| date_with(options, input)),
+		parser(whitespace),
+		parser(symbol),
+		parser(whitespace),
+		parser(|input| amount_in(format, input))
+	)
+		.map(|(_, after_marker, date, after_date, symbol, after_symbol, amount)| Price {
+			date: date,
+			symbol: symbol,
+			amount: amount,
+			spacing: if options.preserve_spacing {
+				Some(PriceSpacing {
+					after_marker: after_marker,
+					after_date: after_date,
+					after_symbol: after_symbol
+				})
+			} else {
+				None
+			}
+		})
+		.parse_state(input)
+}
+
+#[test]
+fn price_with_decimal_comma_and_slash_date() {
+	let options = ParserOptions {
+		allow_slash_dates: true,
+		decimal_comma: true,
+		..ParserOptions::default()
+	};
+	let entries = parse_journal_with("P 2015/10/23 AAPL 313,38 EUR\n", &options).unwrap();
+	match entries[0] {
+		Entry::Price(ref price) => {
+			assert_eq!(price.date.to_string(), "2015/10/23");
+			assert_eq!(price.amount.value, Decimal::new(31338, 2));
+			assert_eq!(price.amount.symbol.value, "EUR");
+		},
+		ref entry => panic!("expected a price, got {:?}", entry)
+	}
+	assert!(parse_journal("P 2015/10/23 AAPL 313,38 EUR\n").is_err());
+}
+
+#[test]
+fn price_quantity_then_symbol() {
+	let result = parser(price)
+		.parse("P 2015-10-25 AAPL 313.38 USD")
+		.map(|x| x.0);
+	let price = result.unwrap();
+	assert_eq!(price.symbol.value, "AAPL");
+	assert_eq!(price.amount, Amount {
+		value: Decimal::new(31338, 2),
+		symbol: Symbol {
+			value: "USD".to_string(),
+			quoted: false
+		},
+		format: AmountFormat::SymbolRightWithSpace
+	});
+}
+
+#[test]
+fn price_quoted_symbols_quantity_then_symbol() {
+	let result = parser(price)
+		.parse("P 2015-10-25 \"MUTF2351\" 5.42 \"CAD 2\"")
+		.map(|x| x.0);
+	let price = result.unwrap();
+	assert_eq!(price.symbol, Symbol {
+		value: "MUTF2351".to_string(),
+		quoted: true
+	});
+	assert_eq!(price.amount.value, Decimal::new(542, 2));
+	assert_eq!(price.amount.symbol.value, "CAD 2");
+}
+
+#[test]
+fn price_test() {
+	let result = parser(price)
+		.parse("P 2015-10-25 \"MUTF2351\" $5.42")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(Price {
+		date: Date {
+			year: 2015,
+			month: 10,
+			day: 25,
+			separator: DateSeparator::Hyphen
+		},
+		symbol: Symbol {
+			value: "MUTF2351".to_string(),
+			quoted: true
+		},
+		amount: Amount {
+			value: Decimal::new(542, 2),
+			symbol: Symbol {
+				value: "$".to_string(),
+				quoted: false
+			},
+			format: AmountFormat::SymbolLeftNoSpace
+		},
+		spacing: None
+	}));
+}
+
+impl FromStr for Price {
+	type Err = WealthError;
+
+	/// Parses a single price entry, requiring the whole string to be consumed.
+	fn from_str(s: &str) -> Result<Price, WealthError> {
+		match parser(price).parse(s) {
+			Ok((price, "")) => Ok(price),
+			Ok((_, rest)) => Err(WealthError::TrailingInput(rest.to_string())),
+			Err(err) => Err(WealthError::Parse(err.to_string()))
+		}
+	}
+}
+
+impl<'a> TryFrom<&'a str> for Price {
+	type Error = WealthError;
+
+	fn try_from(s: &'a str) -> Result<Price, WealthError> {
+		s.parse()
+	}
+}
+
+impl fmt::Display for Price {
+	/// Writes the price back out as a price directive, with the spacing it was
+	/// parsed with when that was kept, otherwise single spaces.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.spacing {
+			Some(ref spacing) => write!(f, "P{}{}{}{}{}{}",
+				spacing.after_marker, self.date,
+				spacing.after_date, self.symbol,
+				spacing.after_symbol, self.amount),
+			None => write!(f, "P {} {} {}", self.date, self.symbol, self.amount)
+		}
+	}
+}
+
+#[test]
+fn price_display_preserve_spacing() {
+	let text = "P 2015-10-23  AAPL       $313.38\nP 2015-10-25  \"MUTF2351\"   $5.42";
+	let options = ParserOptions {
+		preserve_spacing: true,
+		..ParserOptions::default()
+	};
+	let prices = parse_price_db_with(text, &options).unwrap();
+	let lines: Vec<String> = prices.iter().map(Price::to_string).collect();
+	assert_eq!(lines.join("\n"), text);
+
+	let prices = parse_price_db(text).unwrap();
+	assert_eq!(prices[0].to_string(), "P 2015-10-23 AAPL $313.38");
+}
+
+#[test]
+fn price_try_from_valid() {
+	let result = Price::try_from("P 2015-10-25 AAPL $5.42");
+	assert_eq!(result, Ok(Price {
+		date: Date {
+			year: 2015,
+			month: 10,
+			day: 25,
+			separator: DateSeparator::Hyphen
+		},
+		symbol: Symbol {
+			value: "AAPL".to_string(),
+			quoted: false
+		},
+		amount: Amount {
+			value: Decimal::new(542, 2),
+			symbol: Symbol {
+				value: "$".to_string(),
+				quoted: false
+			},
+			format: AmountFormat::SymbolLeftNoSpace
+		},
+		spacing: None
+	}));
+}
+
+#[test]
+fn price_try_from_invalid() {
+	let result = Price::try_from("P 2015-10-25");
+	assert!(matches!(result, Err(WealthError::Parse(_))));
+}
+
+#[test]
+fn price_try_from_trailing_input() {
+	let result = Price::try_from("P 2015-10-25 AAPL $5.42\nP");
+	assert_eq!(result, Err(WealthError::TrailingInput("\nP".to_string())));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn price_serializes_to_json() {
+	let (price, _) = parser(price)
+		.parse("P 2015-10-25 \"MUTF2351\" $5.42")
+		.unwrap();
+	let json = serde_json::to_string(&price).unwrap();
+	assert!(json.contains("\"date\":{\"year\":2015,\"month\":10,\"day\":25}"));
+	assert!(json.contains("\"symbol\":{\"value\":\"MUTF2351\",\"quoted\":true}"));
+}
+
+
+
+/// Parses a price DB file, which contains only price entries and comment lines.
+#[cfg(test)]
+fn price_db<I>(input: State<I>) -> ParseResult<Vec<Price>, I>
+where I: Stream<Item=char> {
+	price_db_with(&ParserOptions::default(), input)
+}
+
+/// Parses a price DB file like `price_db`, within the configured limits.
+fn price_db_with<I>(options: &ParserOptions, input: State<I>) -> ParseResult<Vec<Price>, I>
+where I: Stream<Item=char> {
+	let count = Cell::new(0);
+	sep_end_by(
+		parser(|input| price_with(options, input)).map(Some).or(parser(comment).map(|_| None))
+			.and_then(|price| options.count_entry(&count).map(|_| price)),
+		parser(line_ending))
+		.map(|prices: Vec<Option<Price>>| prices.into_iter().flatten().collect())
+		.parse_state(input)
+}
+
+#[test]
+fn price_db_no_records() {
+	let result = parser(price_db)
+		.parse("")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(vec![]));
+}
+
+#[test]
+fn price_db_one_record() {
+	let result = parser(price_db)
+		.parse("P 2015-10-25 \"MUTF2351\" $5.42")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(vec![
+		Price {
+			date: Date {
+				year: 2015,
+				month: 10,
+				day: 25,
+				separator: DateSeparator::Hyphen
+			},
+			symbol: Symbol {
+				value: "MUTF2351".to_string(),
+				quoted: true
+			},
+			amount: Amount {
+				value: Decimal::new(542, 2),
+				symbol: Symbol {
+					value: "$".to_string(),
+					quoted: false
+				},
+				format: AmountFormat::SymbolLeftNoSpace
+			},
+			spacing: None
+		}
+	]));
+}
+
+#[test]
+fn price_db_multiple_records() {
+	let result = parser(price_db)
+		.parse("\
+			P 2015-10-23 \"MUTF2351\" $5.42\n\
+			P 2015-10-25 \"MUTF2351\" $5.98\n\
+			P 2015-10-25 AAPL $313.38\n\
+		")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(vec![
+		Price {
+			date: Date {
+				year: 2015,
+				month: 10,
+				day: 23,
+				separator: DateSeparator::Hyphen
+			},
+			symbol: Symbol {
+				value: "MUTF2351".to_string(),
+				quoted: true
+			},
+			amount: Amount {
+				value: Decimal::new(542, 2),
+				symbol: Symbol {
+					value: "$".to_string(),
+					quoted: false
+				},
+				format: AmountFormat::SymbolLeftNoSpace
+			},
+			spacing: None
+		},
+		Price {
+			date: Date {
+				year: 2015,
+				month: 10,
+				day: 25,
+				separator: DateSeparator::Hyphen
+			},
+			symbol: Symbol {
+				value: "MUTF2351".to_string(),
+				quoted: true
+			},
+			amount: Amount {
+				value: Decimal::new(598, 2),
+				symbol: Symbol {
+					value: "$".to_string(),
+					quoted: false
+				},
+				format: AmountFormat::SymbolLeftNoSpace
+			},
+			spacing: None
+		},
+		Price {
+			date: Date {
+				year: 2015,
+				month: 10,
+				day: 25,
+				separator: DateSeparator::Hyphen
+			},
+			symbol: Symbol {
+				value: "AAPL".to_string(),
+				quoted: false
+			},
+			amount: Amount {
+				value: Decimal::new(31338, 2),
+				symbol: Symbol {
+					value: "$".to_string(),
+					quoted: false
+				},
+				format: AmountFormat::SymbolLeftNoSpace
+			},
+			spacing: None
+		}
+	]));
+}
+
+#[test]
+fn price_db_comment_lines() {
+	let result = parser(price_db)
+		.parse("\
+			; prices fetched 2015-10-25\n\
+			P 2015-10-23 AAPL $313.38\n\
+			; mutual funds\n\
+			P 2015-10-25 \"MUTF2351\" $5.42\n\
+		")
+		.map(|x| x.0.iter().map(|price| price.symbol.value.clone()).collect::<Vec<_>>());
+	assert_eq!(result, Ok(vec!["AAPL".to_string(), "MUTF2351".to_string()]));
+}
+
+#[test]
+fn price_db_classic_mac_line_endings() {
+	let result = parser(price_db)
+		.parse("P 2015-10-23 AAPL $313.38\rP 2015-10-25 \"MUTF2351\" $5.42\r")
+		.map(|x| x.0.len());
+	assert_eq!(result, Ok(2));
+}
+
+
+
+impl PriceDb {
+	pub fn new(prices: Vec<Price>) -> PriceDb {
+		PriceDb {
+			prices: prices
+		}
+	}
+
+	/// Prices for a symbol between two dates (inclusive), sorted by date.
+	/// Either bound may be left open.
+	pub fn history(&self, symbol: &Symbol, from: Option<Date>, to: Option<Date>) -> Vec<&Price> {
+		let mut history: Vec<&Price> = self.prices.iter()
+			.filter(|price| price.symbol == *symbol)
+			.filter(|price| from.as_ref().is_none_or(|from| price.date >= *from))
+			.filter(|price| to.as_ref().is_none_or(|to| price.date <= *to))
+			.collect();
+		history.sort_by(|a, b| a.date.cmp(&b.date));
+		history
+	}
+
+	/// The most recent price for a symbol on or before a date. Quoted and
+	/// unquoted symbols with the same value are treated as the same symbol.
+	pub fn price_on(&self, symbol: &str, date: &Date) -> Option<&Price> {
+		self.prices.iter()
+			.filter(|price| price.symbol.value == symbol && price.date <= *date)
+			.max_by(|a, b| a.date.cmp(&b.date))
+	}
+
+	/// The total value of the holdings on a date: each quantity times its
+	/// symbol's most recent price on or before the date, summed. None when a
+	/// holding has no price, the prices aren't all in the same commodity, or
+	/// the total is too large to hold.
+	pub fn value_holdings(&self, holdings: &[(Symbol, Decimal)], date: &Date) -> Option<Amount> {
+		let mut total: Option<Amount> = None;
+		for &(ref symbol, quantity) in holdings {
+			let price = &self.price_on(&symbol.value, date)?.amount;
+			let value = quantity.checked_mul(price.value)?;
+			match total {
+				Some(ref mut total) if total.symbol == price.symbol =>
+					total.value = total.value.checked_add(value)?,
+				Some(_) => return None,
+				None => total = Some(Amount {
+					value: value,
+					symbol: price.symbol.clone(),
+					format: price.format
+				})
+			}
+		}
+		total
+	}
+}
+
+impl FromIterator<Price> for PriceDb {
+	fn from_iter<T: IntoIterator<Item=Price>>(iter: T) -> PriceDb {
+		PriceDb::new(iter.into_iter().collect())
+	}
+}
+
+#[test]
+fn price_db_collect() {
+	let db: PriceDb = ["P 2015-10-20 AAPL $310.00", "P 2015-10-23 AAPL $313.38"]
+		.iter()
+		.map(|line| line.parse::<Price>().unwrap())
+		.collect();
+	let on = Date {
+		year: 2015,
+		month: 10,
+		day: 24,
+		separator: DateSeparator::Hyphen
+	};
+	assert_eq!(db.prices.len(), 2);
+	assert_eq!(db.price_on("AAPL", &on).map(|price| price.amount.value), Some(Decimal::new(31338, 2)));
+}
+
+#[test]
+fn price_db_value_holdings() {
+	let db: PriceDb = [
+		"P 2015-10-20 AAPL $310.00",
+		"P 2015-10-23 AAPL $313.38",
+		"P 2015-10-23 \"MUTF2351\" $5.42",
+		"P 2015-10-30 AAPL $320.00"
+	]
+		.iter()
+		.map(|line| line.parse::<Price>().unwrap())
+		.collect();
+	let symbol = |value: &str| Symbol {
+		value: value.to_string(),
+		quoted: false
+	};
+	let on = Date {
+		year: 2015,
+		month: 10,
+		day: 24,
+		separator: DateSeparator::Hyphen
+	};
+	let holdings = [(symbol("AAPL"), Decimal::new(10, 0)), (symbol("MUTF2351"), Decimal::new(1005, 1))];
+	let total = db.value_holdings(&holdings, &on).unwrap();
+	assert_eq!(total.to_string(), "$3678.510");
+
+	let holdings = [(symbol("AAPL"), Decimal::new(10, 0)), (symbol("GOOG"), Decimal::new(1, 0))];
+	assert_eq!(db.value_holdings(&holdings, &on), None);
+
+	let holdings = [(symbol("AAPL"), Decimal::MAX)];
+	assert_eq!(db.value_holdings(&holdings, &on), None);
+
+	let quantity = Decimal::MAX / Decimal::new(400, 0);
+	let holdings = [(symbol("AAPL"), quantity), (symbol("AAPL"), quantity)];
+	assert_eq!(db.value_holdings(&holdings, &on), None);
+}
+
+#[test]
+fn price_db_history_in_window() {
+	let (prices, _) = parser(price_db)
+		.parse("\
+			P 2015-10-26 AAPL $315.00\n\
+			P 2015-10-20 AAPL $310.00\n\
+			P 2015-10-23 AAPL $313.38\n\
+			P 2015-10-23 \"MUTF2351\" $5.42\n\
+			P 2015-10-30 AAPL $320.00\n\
+		")
+		.unwrap();
+	let db = PriceDb::new(prices);
+	let symbol = Symbol {
+		value: "AAPL".to_string(),
+		quoted: false
+	};
+	let from = Date {
+		year: 2015,
+		month: 10,
+		day: 21,
+		separator: DateSeparator::Hyphen
+	};
+	let to = Date {
+		year: 2015,
+		month: 10,
+		day: 26,
+		separator: DateSeparator::Hyphen
+	};
+	let result: Vec<Decimal> = db.history(&symbol, Some(from), Some(to))
+		.iter()
+		.map(|price| price.amount.value)
+		.collect();
+	assert_eq!(result, vec![Decimal::new(31338, 2), Decimal::new(31500, 2)]);
+}
+
+#[test]
+fn price_db_history_open_ended() {
+	let (prices, _) = parser(price_db)
+		.parse("\
+			P 2015-10-26 AAPL $315.00\n\
+			P 2015-10-20 AAPL $310.00\n\
+			P 2015-10-30 AAPL $320.00\n\
+		")
+		.unwrap();
+	let db = PriceDb::new(prices);
+	let symbol = Symbol {
+		value: "AAPL".to_string(),
+		quoted: false
+	};
+	let from = Date {
+		year: 2015,
+		month: 10,
+		day: 21,
+		separator: DateSeparator::Hyphen
+	};
+	let result: Vec<Decimal> = db.history(&symbol, Some(from), None)
+		.iter()
+		.map(|price| price.amount.value)
+		.collect();
+	assert_eq!(result, vec![Decimal::new(31500, 2), Decimal::new(32000, 2)]);
+}
+
+#[test]
+fn price_db_price_on() {
+	let (prices, _) = parser(price_db)
+		.parse("\
+			P 2015-10-26 \"AAPL\" $315.00\n\
+			P 2015-10-20 AAPL $310.00\n\
+			P 2015-10-23 AAPL $313.38\n\
+			P 2015-10-24 \"MUTF2351\" $5.42\n\
+		")
+		.unwrap();
+	let db = PriceDb::new(prices);
+	let price_on = |year, month, day| db.price_on("AAPL", &Date {
+		year: year,
+		month: month,
+		day: day,
+		separator: DateSeparator::Hyphen
+	}).map(|price| price.amount.value);
+	assert_eq!(price_on(2015, 10, 19), None);
+	assert_eq!(price_on(2015, 10, 23), Some(Decimal::new(31338, 2)));
+	assert_eq!(price_on(2015, 10, 25), Some(Decimal::new(31338, 2)));
+	assert_eq!(price_on(2015, 11, 1), Some(Decimal::new(31500, 2)));
+}
+
+
+
+/// Splits source text into low-level tokens, one line at a time. Unlike the
+/// parsers this never fails: text it does not recognise is skipped.
+pub fn tokenize<'a>(input: &'a str) -> impl Iterator<Item=Token> + 'a {
+	lines_with_offsets(input)
+		.flat_map(|(offset, line)| tokenize_line(offset, line))
+}
+
+/// Splits text into lines along with the byte offset each starts at. Lines
+/// end at \r\n, \n or a bare \r, and keep their line ending.
+fn lines_with_offsets<'a>(input: &'a str) -> impl Iterator<Item=(usize, &'a str)> + 'a {
+	let mut offset = 0;
+	iter::from_fn(move || {
+		let rest = &input[offset..];
+		if rest.is_empty() {
+			return None;
+		}
+		let end = match rest.find(['\r', '\n']) {
+			Some(index) if rest[index..].starts_with("\r\n") => index + 2,
+			Some(index) => index + 1,
+			None => rest.len()
+		};
+		let start = offset;
+		offset += end;
+		Some((start, &rest[..end]))
+	})
+}
+
+/// Tokenizes a single line starting at the given byte offset.
+fn tokenize_line(offset: usize, line: &str) -> Vec<Token> {
+	let line = line.trim_end_matches(['\r', '\n']);
+	let mut tokens = Vec::new();
+	let mut scanner = Scanner { line: line, pos: 0 };
+	let mut push = |kind, span: Range<usize>| {
+		tokens.push(Token {
+			kind: kind,
+			span: offset + span.start .. offset + span.end
+		});
+	};
+
+	match scanner.peek() {
+		None => {},
+		Some(';') | Some('#') | Some('%') | Some('|') | Some('*') => {
+			push(TokenKind::Comment, 0..line.len());
+		},
+		Some(c) if c.is_ascii_digit() => {
+			push(TokenKind::Date, scanner.take_while(|c| !c.is_whitespace()));
+			scanner.take_while(|c| c != ';');
+			scan_comment(&mut scanner, &mut push);
+		},
+		Some(c) if c.is_alphabetic() => {
+			let keyword = scanner.take_while(|c| c.is_alphanumeric());
+			let is_price = &line[keyword.clone()] == "P";
+			push(TokenKind::Keyword, keyword);
+			if is_price {
+				scanner.take_while(char::is_whitespace);
+				push(TokenKind::Date, scanner.take_while(|c| !c.is_whitespace()));
+				scan_amounts(&mut scanner, &mut push);
+			} else {
+				scanner.take_while(|c| c != ';');
+				scan_comment(&mut scanner, &mut push);
+			}
+		},
+		Some(_) => {
+			scanner.take_while(char::is_whitespace);
+			if scanner.peek().is_some_and(|c| c != ';') {
+				push(TokenKind::Account, scanner.take_account());
+			}
+			scan_amounts(&mut scanner, &mut push);
+		}
+	}
+
+	tokens
+}
+
+/// Tokenizes the amounts, symbols and trailing comment at the end of a posting
+/// or price line.
+fn scan_amounts<F>(scanner: &mut Scanner, push: &mut F)
+where F: FnMut(TokenKind, Range<usize>) {
+	loop {
+		scanner.take_while(char::is_whitespace);
+		match scanner.peek() {
+			None | Some(';') => break,
+			Some('"') => {
+				let start = scanner.pos;
+				scanner.advance();
+				scanner.take_while(|c| c != '"');
+				scanner.advance();
+				push(TokenKind::Symbol, start..scanner.pos);
+			},
+			Some(c) if c.is_ascii_digit() || (c == '-' && scanner.next_is_digit()) => {
+				scanner.advance();
+				let rest = scanner.take_while(|c| c.is_ascii_digit() || c == ',' || c == '.');
+				push(TokenKind::Amount, rest.start - 1 .. rest.end);
+			},
+			Some(c) if "-@{}=()[]".contains(c) => scanner.advance(),
+			Some(_) => {
+				let symbol = scanner.take_while(|c| {
+					!c.is_whitespace() && !c.is_ascii_digit() && !"-;\"@{}=()[]".contains(c)
+				});
+				// Always move on, so the scan can't stall on a character
+				// nothing else takes.
+				if symbol.is_empty() {
+					scanner.advance();
+				} else {
+					push(TokenKind::Symbol, symbol);
+				}
+			}
+		}
+	}
+	scan_comment(scanner, push);
+}
+
+/// Tokenizes a trailing comment, if the scanner is positioned on one.
+fn scan_comment<F>(scanner: &mut Scanner, push: &mut F)
+where F: FnMut(TokenKind, Range<usize>) {
+	if scanner.peek() == Some(';') {
+		push(TokenKind::Comment, scanner.pos..scanner.line.len());
+		scanner.pos = scanner.line.len();
+	}
+}
+
+/// Cursor over a single line used by the tokenizer.
+struct Scanner<'a> {
+	line: &'a str,
+	pos: usize
+}
+
+impl<'a> Scanner<'a> {
+	fn peek(&self) -> Option<char> {
+		self.line[self.pos..].chars().next()
+	}
+
+	fn next_is_digit(&self) -> bool {
+		self.line[self.pos..].chars().nth(1).is_some_and(|c| c.is_ascii_digit())
+	}
+
+	fn advance(&mut self) {
+		if let Some(c) = self.peek() {
+			self.pos += c.len_utf8();
+		}
+	}
+
+	fn take_while<F>(&mut self, mut predicate: F) -> Range<usize>
+	where F: FnMut(char) -> bool {
+		let start = self.pos;
+		while let Some(c) = self.peek() {
+			if !predicate(c) {
+				break;
+			}
+			self.pos += c.len_utf8();
+		}
+		start..self.pos
+	}
+
+	/// Takes an account name, which ends at a tab, two spaces, or a comment.
+	fn take_account(&mut self) -> Range<usize> {
+		let start = self.pos;
+		while let Some(c) = self.peek() {
+			let rest = &self.line[self.pos..];
+			if c == '\t' || c == ';' || rest.starts_with("  ") || rest.trim().is_empty() {
+				break;
+			}
+			self.pos += c.len_utf8();
+		}
+		self.pos = start + self.line[start..self.pos].trim_end().len();
+		start..self.pos
+	}
+}
+
+#[test]
+fn tokenize_transaction() {
+	let tokens: Vec<Token> = tokenize("\
+		2015-10-20 * Payee ;Comment\n\
+		\tAssets:Savings  $45.00\n\
+		\tIncome:Salary\n\
+	").collect();
+	assert_eq!(tokens, vec![
+		Token { kind: TokenKind::Date, span: 0..10 },
+		Token { kind: TokenKind::Comment, span: 19..27 },
+		Token { kind: TokenKind::Account, span: 29..43 },
+		Token { kind: TokenKind::Symbol, span: 45..46 },
+		Token { kind: TokenKind::Amount, span: 46..51 },
+		Token { kind: TokenKind::Account, span: 53..66 }
+	]);
+}
+
+#[test]
+fn tokenize_price() {
+	let tokens: Vec<Token> = tokenize("P 2015-10-25 \"MUTF2351\" 5.42 USD").collect();
+	assert_eq!(tokens, vec![
+		Token { kind: TokenKind::Keyword, span: 0..1 },
+		Token { kind: TokenKind::Date, span: 2..12 },
+		Token { kind: TokenKind::Symbol, span: 13..23 },
+		Token { kind: TokenKind::Amount, span: 24..28 },
+		Token { kind: TokenKind::Symbol, span: 29..32 }
+	]);
+}
+
+#[test]
+fn tokenize_tolerates_malformed_posting() {
+	let tokens: Vec<Token> = tokenize("\tAssets:Cash  {@ $ ; note").collect();
+	assert_eq!(tokens, vec![
+		Token { kind: TokenKind::Account, span: 1..12 },
+		Token { kind: TokenKind::Symbol, span: 17..18 },
+		Token { kind: TokenKind::Comment, span: 19..25 }
+	]);
+}
+
+#[test]
+fn tokenize_non_breaking_space() {
+	let tokens: Vec<Token> = tokenize("    Assets:Cash  $\u{00A0}5.00\n").collect();
+	assert_eq!(tokens, vec![
+		Token { kind: TokenKind::Account, span: 4..15 },
+		Token { kind: TokenKind::Symbol, span: 17..18 },
+		Token { kind: TokenKind::Amount, span: 20..24 }
+	]);
+}
+
+#[test]
+fn tokenize_bare_carriage_returns() {
+	let tokens: Vec<Token> = tokenize("P 2015-10-23 AAPL $313.38\rP 2015-10-25 AAPL $5.42\r").collect();
+	assert_eq!(tokens, vec![
+		Token { kind: TokenKind::Keyword, span: 0..1 },
+		Token { kind: TokenKind::Date, span: 2..12 },
+		Token { kind: TokenKind::Symbol, span: 13..17 },
+		Token { kind: TokenKind::Symbol, span: 18..19 },
+		Token { kind: TokenKind::Amount, span: 19..25 },
+		Token { kind: TokenKind::Keyword, span: 26..27 },
+		Token { kind: TokenKind::Date, span: 28..38 },
+		Token { kind: TokenKind::Symbol, span: 39..43 },
+		Token { kind: TokenKind::Symbol, span: 44..45 },
+		Token { kind: TokenKind::Amount, span: 45..49 }
+	]);
+}
+
+
+
+/// What earlier directives in a journal say about the entries that follow.
+struct JournalState {
+	/// Year set by the most recent Y directive.
+	year: Cell<Option<i32>>,
+	/// Tags of the apply tag blocks currently open, outermost first.
+	applied_tags: RefCell<Vec<String>>
+}
+
+impl JournalState {
+	fn new(year: Option<i32>) -> JournalState {
+		JournalState {
+			year: Cell::new(year),
+			applied_tags: RefCell::new(Vec::new())
+		}
+	}
+}
+
+/// Parses a single journal entry, applying and updating the journal state
+/// when the entry is a Y or apply tag directive.
+fn entry<I>(options: &ParserOptions, state: &JournalState, input: State<I>) -> ParseResult<Entry, I>
+where I: Stream<Item=char> {
+	parser(|input| transaction_in_year(options, state.year.get(), input))
+		.map(|mut transaction| {
+			transaction.applied_tags = state.applied_tags.borrow().clone();
+			Entry::Transaction(transaction)
+		})
+		.or(parser(|input| price_with(options, input)).map(Entry::Price))
+		.or(parser(|input| periodic_transaction(options, input)).map(Entry::Periodic))
+		.or(parser(include_directive).map(Entry::Include))
+		.or(parser(commodity_directive).map(Entry::Commodity))
+		.or(parser(|input| account_directive(options, input)).map(Entry::Account))
+		.or(parser(default_commodity_directive).map(Entry::DefaultCommodity))
+		.or(parser(year_directive).map(|y| {
+			state.year.set(Some(y));
+			Entry::Year(y)
+		}))
+		.or(parser(apply_tag_directive).map(|tag| {
+			state.applied_tags.borrow_mut().push(tag.clone());
+			Entry::ApplyTag(tag)
+		}))
+		.or(parser(end_apply_tag_directive).map(|_| {
+			state.applied_tags.borrow_mut().pop();
+			Entry::EndApplyTag
+		}))
+		.or(parser(line_comment).map(Entry::Comment))
+		.parse_state(input)
+}
+
+
+
+/// Parses a journal file, made up of entries separated by line endings.
+#[cfg(test)]
+fn journal<I>(options: &ParserOptions, input: State<I>) -> ParseResult<Vec<Entry>, I>
+where I: Stream<Item=char> {
+	journal_in_state(options, &JournalState::new(options.default_year), input)
+}
+
+/// Parses a journal file like `journal`, starting from the given state, which
+/// is updated by the directives parsed.
+fn journal_in_state<I>(options: &ParserOptions, state: &JournalState, input: State<I>)
+	-> ParseResult<Vec<Entry>, I>
+where I: Stream<Item=char> {
+	parser(|input| journal_spans(options, state, input))
+		.map(|entries: Vec<(Span, Entry)>| entries.into_iter().map(|(_, entry)| entry).collect())
+		.parse_state(input)
+}
+
+/// Parses a journal file like `journal_in_state`, pairing each entry with the
+/// lines it was parsed from.
+fn journal_spans<I>(options: &ParserOptions, state: &JournalState, input: State<I>)
+	-> ParseResult<Vec<(Span, Entry)>, I>
+where I: Stream<Item=char> {
+	let count = Cell::new(0);
+	skip_many(parser(line_ending))
+		.with(sep_end_by(
+			(
+				parser(line_number),
+				parser(|input| entry(options, state, input)),
+				parser(line_number)
+			)
+				.map(|(start_line, entry, end_line)| (Span {
+					start_line: start_line,
+					end_line: end_line
+				}, entry))
+				.and_then(|entry| options.count_entry(&count).map(|_| entry)),
+			skip_many1(parser(line_ending))))
+		.parse_state(input)
+}
+
+#[test]
+fn journal_empty() {
+	let result = parser(|input| journal(&ParserOptions::default(), input))
+		.parse("")
+		.map(|x| x.0);
+	assert_eq!(result, Ok(vec![]));
+}
+
+#[test]
+fn journal_comment_lines() {
+	let result = parser(|input| journal(&ParserOptions::default(), input))
+		.parse("# Groceries\n\
+			2015-10-20 * Grocery Store\n\
+			\tExpenses:Food  $45.00\n\
+			\tAssets:Cash\n\
+			% budget\n")
+		.map(|x| x.0);
+	let entries = result.unwrap();
+	assert_eq!(entries.len(), 3);
+	assert_eq!(entries[0], Entry::Comment(" Groceries".to_string()));
+	assert!(matches!(entries[1], Entry::Transaction(_)));
+	assert_eq!(entries[2], Entry::Comment(" budget".to_string()));
+}
+
+#[test]
+fn journal_apply_tag_blocks() {
+	let result = parser(|input| journal(&ParserOptions::default(), input))
+		.parse("\
+			apply tag trip\n\
+			2015-10-20 * Hotel\n\
+			\tExpenses:Lodging  $120.00\n\
+			\tAssets:Cash\n\
+			apply tag reimbursable\n\
+			2015-10-21 * Taxi\n\
+			\tExpenses:Transport  $30.00\n\
+			\tAssets:Cash\n\
+			end apply tag\n\
+			end apply tag\n\
+			2015-10-22 * Grocery Store\n\
+			\tExpenses:Food  $45.00\n\
+			\tAssets:Cash\n")
+		.map(|x| x.0);
+	let applied_tags: Vec<Vec<String>> = result.unwrap().into_iter()
+		.filter_map(|entry| match entry {
+			Entry::Transaction(transaction) => Some(transaction.applied_tags),
+			_ => None
+		})
+		.collect();
+	assert_eq!(applied_tags, vec![
+		vec!["trip".to_string()],
+		vec!["trip".to_string(), "reimbursable".to_string()],
+		vec![]
+	]);
+}
+
+#[test]
+fn journal_mixed_entries() {
+	let result = parser(|input| journal(&ParserOptions::default(), input))
+		.parse("\
+			include prices.ledger\n\
+			\n\
+			2015-10-20 * Grocery Store\n\
+			\tExpenses:Food  $45.00\n\
+			\tAssets:Cash\n\
+			\n\
+			P 2015-10-25 AAPL $313.38\n\
+		")
+		.map(|x| x.0);
+	let entries = result.unwrap();
+	assert_eq!(entries.len(), 3);
+	assert_eq!(entries[0], Entry::Include("prices.ledger".to_string()));
+	assert!(matches!(entries[1], Entry::Transaction(ref t) if t.postings.len() == 2));
+	assert!(matches!(entries[2], Entry::Price(_)));
+}
+
+#[test]
+fn journal_default_commodity() {
+	let result = parser(|input| journal(&ParserOptions::default(), input))
+		.parse("D $1,000.00\nP 2015-10-25 AAPL $313.38\n")
+		.map(|x| x.0);
+	let entries = result.unwrap();
+	assert_eq!(entries.len(), 2);
+	assert!(matches!(entries[0], Entry::DefaultCommodity(ref amount) if amount.symbol.value == "$"));
+}
+
+#[test]
+fn journal_year_directive() {
+	let result = parser(|input| journal(&ParserOptions::default(), input))
+		.parse("\
+			Y 2015\n\
+			\n\
+			10/17 * Grocery Store\n\
+			\tExpenses:Food  $45.00\n\
+			\tAssets:Cash\n\
+		")
+		.map(|x| x.0);
+	let entries = result.unwrap();
+	assert_eq!(entries[0], Entry::Year(2015));
+	match entries[1] {
+		Entry::Transaction(ref transaction) => assert_eq!(transaction.header.date, Date {
+			year: 2015,
+			month: 10,
+			day: 17,
+			separator: DateSeparator::Hyphen
+		}),
+		_ => panic!("expected a transaction")
+	}
+}
+
+
+
+/// Parses one or more indented lines found where a journal entry should start,
+/// returning the line number of the first.
+fn orphan_postings<I>(input: State<I>) -> ParseResult<i32, I>
+where I: Stream<Item=char> {
+	let indented_line = || {
+		(
+			parser(whitespace),
+			skip_many1(satisfy(|c| c != '\r' && c != '\n'))
+		)
+	};
+	(
+		parser(line_number),
+		indented_line(),
+		skip_many(try((parser(line_ending), indented_line())))
+	)
+		.map(|(line_num, _, _)| line_num)
+		.parse_state(input)
+}
+
+#[test]
+fn orphan_postings_test() {
+	let result = parser(orphan_postings)
+		.parse("    Expenses:Food  $5.00\n    Assets:Cash\n2015-10-20 * Payee");
+	assert_eq!(result, Ok((1, "\n2015-10-20 * Payee")));
+}
+
+
+
+/// Parses a journal file like `journal_in_state`, but recovers from postings
+/// that appear before any transaction header: each run of them is reported as
+/// an error, in order among the entries, and parsing resumes at the next entry.
+fn journal_lenient<I>(options: &ParserOptions, state: &JournalState, input: State<I>)
+	-> ParseResult<Vec<Result<Entry, WealthError>>, I>
+where I: Stream<Item=char> {
+	let count = Cell::new(0);
+	skip_many(parser(line_ending))
+		.with(sep_end_by(
+			parser(|input| entry(options, state, input))
+				.and_then(|entry| options.count_entry(&count).map(|_| entry))
+				.map(Ok)
+				.or(parser(orphan_postings)
+					.map(|line_num| Err(WealthError::OrphanPosting(line_num)))),
+			skip_many1(parser(line_ending))))
+		.parse_state(input)
+}
+
+#[test]
+fn journal_lenient_orphan_posting() {
+	let state = JournalState::new(None);
+	let result = parser(|input| journal_lenient(&ParserOptions::default(), &state, input))
+		.parse("\
+			\tExpenses:Food  $5.00\n\
+			2015-10-20 * Grocery Store\n\
+			\tExpenses:Food  $45.00\n\
+			\tAssets:Cash\n\
+		")
+		.map(|x| x.0);
+	let results = result.unwrap();
+	assert_eq!(results.len(), 2);
+	assert_eq!(results[0], Err(WealthError::OrphanPosting(1)));
+	assert!(results[1].is_ok());
+}
+
+
+
+/// Parses the text of a journal file into its entries, using the default
+/// parser options. The whole input must be consumed.
+pub fn parse_journal(input: &str) -> Result<Vec<Entry>, ParseFailure> {
+	parse_journal_with(input, &ParserOptions::default())
+}
+
+/// Parses the text of a journal file like `parse_journal`, using the given
+/// options.
+pub fn parse_journal_with(input: &str, options: &ParserOptions) -> Result<Vec<Entry>, ParseFailure> {
+	parse_journal_in_state(input, options, &JournalState::new(options.default_year))
+}
+
+/// Parses the text of a journal file like `parse_journal_with`, pairing each
+/// entry with the lines of the source it was parsed from.
+pub fn parse_journal_spans(input: &str, options: &ParserOptions)
+	-> Result<Vec<(Span, Entry)>, ParseFailure> {
+	let state = JournalState::new(options.default_year);
+	parser(|input| journal_spans(options, &state, input))
+		.skip(not_followed_by(any()))
+		.parse(input)
+		.map(|x| x.0)
+		.map_err(ParseFailure::from)
+}
+
+#[test]
+fn parse_journal_spans_test() {
+	let result = parse_journal_spans("\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Cash\n\
+		\n\
+		; gas\n\
+		2015-10-21 * Gas Station\n\
+		\tExpenses:Auto  $30.00\n\
+		\tAssets:Cash\n\
+	", &ParserOptions::default());
+	let spans: Vec<Span> = result.unwrap().into_iter().map(|(span, _)| span).collect();
+	assert_eq!(spans, vec![
+		Span { start_line: 1, end_line: 3 },
+		Span { start_line: 5, end_line: 5 },
+		Span { start_line: 6, end_line: 8 }
+	]);
+}
+
+/// Parses the text of a journal file like `parse_journal_with`, starting from
+/// the given state, which is updated by the directives parsed.
+fn parse_journal_in_state(input: &str, options: &ParserOptions, state: &JournalState)
+	-> Result<Vec<Entry>, ParseFailure> {
+	parser(|input| journal_in_state(options, state, input))
+		.skip(not_followed_by(any()))
+		.parse(input)
+		.map(|x| x.0)
+		.map_err(ParseFailure::from)
+}
+
+/// Parses the text of a journal file like `parse_journal_in_state`, but
+/// recovering from postings without a transaction header.
+fn parse_journal_lenient_in_state(input: &str, options: &ParserOptions, state: &JournalState)
+	-> Result<Vec<Result<Entry, WealthError>>, ParseFailure> {
+	parser(|input| journal_lenient(options, state, input))
+		.skip(not_followed_by(any()))
+		.parse(input)
+		.map(|x| x.0)
+		.map_err(ParseFailure::from)
+}
+
+#[test]
+fn parse_journal_test() {
+	let result = parse_journal("\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Cash\n\
+		\n\
+		P 2015-10-25 AAPL $313.38\n\
+	");
+	assert_eq!(result.map(|entries| entries.len()), Ok(2));
+}
+
+#[test]
+fn parse_journal_unparsed_text_is_error() {
+	let result = parse_journal("P 2015-10-25 AAPL $313.38\n!oops\n");
+	assert!(result.is_err());
+}
+
+#[test]
+fn parse_journal_max_entries() {
+	let options = ParserOptions {
+		max_entries: Some(1),
+		..ParserOptions::default()
+	};
+	let result = parse_journal_with("\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Cash\n\
+		\n\
+		P 2015-10-25 AAPL $313.38\n\
+	", &options);
+	assert!(result.is_err());
+}
+
+#[test]
+fn parse_journal_with_decimal_comma() {
+	let text = "\
+		2015-10-20 * Supermarché\n\
+		\tExpenses:Food  1.234,56 €\n\
+		\tAssets:Cash\n\
+	";
+	assert!(parse_journal(text).is_err());
+
+	let options = ParserOptions {
+		decimal_comma: true,
+		..ParserOptions::default()
+	};
+	match parse_journal_with(text, &options).unwrap()[0] {
+		Entry::Transaction(ref transaction) => assert_eq!(
+			transaction.postings[0].amount.as_ref().map(|amount| amount.value),
+			Some(Decimal::new(123456, 2))),
+		ref entry => panic!("expected a transaction, got {:?}", entry)
+	}
+}
+
+#[test]
+fn parse_journal_with_default_year() {
+	let text = "\
+		10/20 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Cash\n\
+	";
+	assert!(parse_journal(text).is_err());
+
+	let options = ParserOptions {
+		default_year: Some(2015),
+		..ParserOptions::default()
+	};
+	match parse_journal_with(text, &options).unwrap()[0] {
+		Entry::Transaction(ref transaction) => assert_eq!(transaction.header.date, Date {
+			year: 2015,
+			month: 10,
+			day: 20,
+			separator: DateSeparator::Hyphen
+		}),
+		ref entry => panic!("expected a transaction, got {:?}", entry)
+	}
+}
+
+
+
+/// Parses the text of a price DB file into its prices. The whole input must be
+/// consumed.
+///
+/// ```
+/// use wealth_pulse::parse_price_db;
+///
+/// let prices = parse_price_db("P 2015-10-25 \"MUTF2351\" $5.42\n").unwrap();
+/// assert_eq!(prices.len(), 1);
+/// assert_eq!(prices[0].symbol.value, "MUTF2351");
+/// assert_eq!(prices[0].amount.to_string(), "$5.42");
+/// ```
+pub fn parse_price_db(input: &str) -> Result<Vec<Price>, ParseFailure> {
+	parse_price_db_with(input, &ParserOptions::default())
+}
+
+/// Parses the text of a price DB file like `parse_price_db`, using the given
+/// options.
+pub fn parse_price_db_with(input: &str, options: &ParserOptions) -> Result<Vec<Price>, ParseFailure> {
+	parser(|input| price_db_with(options, input))
+		.skip(not_followed_by(any()))
+		.parse(input)
+		.map(|x| x.0)
+		.map_err(ParseFailure::from)
+}
+
+#[test]
+fn parse_price_db_max_entries() {
+	let options = ParserOptions {
+		max_entries: Some(2),
+		..ParserOptions::default()
+	};
+	let text = "\
+		P 2015-10-23 AAPL $313.38\n\
+		P 2015-10-24 AAPL $314.00\n\
+		P 2015-10-25 AAPL $315.50\n\
+	";
+	assert_eq!(parse_price_db_with(&text[..52], &options).map(|prices| prices.len()), Ok(2));
+	let result = parse_price_db_with(text, &options)
+		.map_err(|failure| (failure.line, failure.message));
+	assert_eq!(result, Err((3, "More than 2 entries".to_string())));
+}
+
+/// Parses a quantity, using a decimal point and comma grouping. The whole
+/// input must be consumed.
+pub fn parse_quantity(input: &str) -> Result<Decimal, ParseFailure> {
+	parser(quantity)
+		.skip(not_followed_by(any()))
+		.parse(input)
+		.map(|x| x.0)
+		.map_err(ParseFailure::from)
+}
+
+#[test]
+fn parse_quantity_test() {
+	assert_eq!(parse_quantity("-1,234.56"), Ok(Decimal::new(-123456, 2)));
+	assert!(parse_quantity("1.2.3").is_err());
+}
+
+#[test]
+fn parse_price_db_unparsed_text_is_error() {
+	let result = parse_price_db("P 2015-10-25 AAPL $313.38\nnot a price\n");
+	assert!(result.is_err());
+}
+
+#[test]
+fn parse_price_db_failure_position() {
+	let result = parse_price_db("P 2015-10-25 AAPL $313.38\nP 2015-1x-25 AAPL $5.42\n")
+		.map_err(|failure| (failure.line, failure.column));
+	assert_eq!(result, Err((2, 9)));
+}
+
+
+
+/// Reads and parses a journal file. A UTF-8 byte order mark at the start of
+/// the file is ignored.
+pub fn parse_journal_file<P: AsRef<Path>>(path: P) -> Result<Vec<Entry>, JournalError> {
+	let mut contents = String::new();
+	File::open(path)?.read_to_string(&mut contents)?;
+	let contents = contents.strip_prefix('\u{FEFF}').unwrap_or(&contents);
+	parse_journal(contents).map_err(JournalError::Parse)
+}
+
+#[test]
+fn parse_journal_file_with_bom() {
+	let path = std::env::temp_dir().join("wealth_pulse_journal_with_bom.ledger");
+	std::fs::write(&path, "\u{FEFF}P 2015-10-25 AAPL $313.38\n").unwrap();
+	let result = parse_journal_file(&path);
+	std::fs::remove_file(&path).unwrap();
+	let entries = result.unwrap();
+	assert_eq!(entries.len(), 1);
+	assert!(matches!(entries[0], Entry::Price(_)));
+}
+
+#[test]
+fn parse_journal_file_missing() {
+	let path = std::env::temp_dir().join("wealth_pulse_no_such_journal.ledger");
+	let result = parse_journal_file(&path);
+	assert!(matches!(result, Err(JournalError::Io(_))));
+}
+
+
+
+/// Parses a journal from a reader one block of lines at a time, where blocks
+/// are separated by blank lines, so only one block is held in memory.
+pub fn parse_journal_reader<R: BufRead>(reader: R) -> impl Iterator<Item=Result<Entry, ParseFailure>> {
+	JournalReader {
+		lines: reader.lines(),
+		line_number: 0,
+		state: JournalState::new(None),
+		pending: VecDeque::new(),
+		lenient: false,
+		done: false
+	}
+}
+
+struct JournalReader<R> {
+	lines: io::Lines<R>,
+	/// Number of lines read so far.
+	line_number: i32,
+	/// Directives in effect, carried from one block to the next.
+	state: JournalState,
+	/// Entries parsed from the current block that haven't been returned yet,
+	/// along with any errors recovered from in it.
+	pending: VecDeque<Result<Entry, ParseFailure>>,
+	/// Whether to recover from postings without a transaction header, and
+	/// carry on with the next block after one fails to parse.
+	lenient: bool,
+	done: bool
+}
+
+impl<R: BufRead> Iterator for JournalReader<R> {
+	type Item = Result<Entry, ParseFailure>;
+
+	fn next(&mut self) -> Option<Result<Entry, ParseFailure>> {
+		loop {
+			if let Some(result) = self.pending.pop_front() {
+				return Some(result);
+			}
+			if self.done {
+				return None;
+			}
+
+			let mut start = self.line_number;
+			let mut block = String::new();
+			loop {
+				match self.lines.next() {
+					None => {
+						self.done = true;
+						break;
+					},
+					Some(Err(err)) => {
+						self.done = true;
+						return Some(Err(ParseFailure {
+							line: self.line_number + 1,
+							column: 1,
+							message: err.to_string()
+						}));
+					},
+					Some(Ok(line)) => {
+						self.line_number += 1;
+						if !line.trim().is_empty() {
+							block.push_str(&line);
+							block.push('\n');
+						} else if block.is_empty() {
+							start = self.line_number;
+						} else {
+							break;
+						}
+					}
+				}
+			}
+
+			let options = ParserOptions::default();
+			let parsed = if self.lenient {
+				parse_journal_lenient_in_state(&block, &options, &self.state)
+			} else {
+				parse_journal_in_state(&block, &options, &self.state)
+					.map(|entries| entries.into_iter().map(Ok).collect())
+			};
+			match parsed {
+				Ok(results) => for result in results {
+					self.pending.push_back(match result {
+						Ok(mut entry) => {
+							offset_line_numbers(&mut entry, start);
+							Ok(entry)
+						},
+						Err(WealthError::OrphanPosting(line)) => Err(ParseFailure {
+							line: line + start,
+							column: 1,
+							message: WealthError::OrphanPosting(line + start).to_string()
+						}),
+						Err(error) => Err(ParseFailure {
+							line: start + 1,
+							column: 1,
+							message: error.to_string()
+						})
+					});
+				},
+				Err(mut failure) => {
+					self.done = self.done || !self.lenient;
+					failure.line += start;
+					return Some(Err(failure));
+				}
+			}
+		}
+	}
+}
+
+/// Parses the text of a journal file one block at a time, where blocks are
+/// separated by blank lines. Postings without a transaction header are
+/// reported and skipped up to the next entry, and a block that otherwise
+/// fails to parse is recorded and skipped, so every problem is reported along
+/// with all the entries that did parse.
+pub fn parse_journal_lenient(input: &str) -> (Vec<Entry>, Vec<ParseFailure>) {
+	let reader = JournalReader {
+		lines: io::Cursor::new(input).lines(),
+		line_number: 0,
+		state: JournalState::new(None),
+		pending: VecDeque::new(),
+		lenient: true,
+		done: false
+	};
+	let mut entries = Vec::new();
+	let mut failures = Vec::new();
+	for result in reader {
+		match result {
+			Ok(entry) => entries.push(entry),
+			Err(failure) => failures.push(failure)
+		}
+	}
+	(entries, failures)
+}
+
+#[test]
+fn parse_journal_lenient_skips_bad_block() {
+	let (entries, failures) = parse_journal_lenient("\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Cash\n\
+		\n\
+		2015-10-21 * Gas Station\n\
+		\tExpenses:Auto  $30.00 $\n\
+		\tAssets:Cash\n\
+		\n\
+		2015-10-22 * Grocery Store\n\
+		\tExpenses:Food  $52.10\n\
+		\tAssets:Cash\n\
+	");
+	assert_eq!(entries.len(), 2);
+	assert_eq!(failures.len(), 1);
+	assert_eq!(failures[0].line, 6);
+}
+
+#[test]
+fn parse_journal_lenient_orphan_posting() {
+	let (entries, failures) = parse_journal_lenient("\
+		2015-10-19 * Paycheque\n\
+		\tAssets:Cash  $100.00\n\
+		\tIncome:Salary\n\
+		\n\
+		\tA:B  $1\n\
+		2015-10-20 P\n\
+		\tA:B  $1\n\
+		\tC\n\
+	");
+	assert_eq!(entries.len(), 2);
+	assert_eq!(failures, vec![ParseFailure {
+		line: 5,
+		column: 1,
+		message: "Posting on line 5 has no transaction header".to_string()
+	}]);
+	match entries[1] {
+		Entry::Transaction(ref transaction) => assert_eq!(transaction.header.line_number, 6),
+		ref entry => panic!("expected a transaction, got {:?}", entry)
+	}
+
+	let (entries, failures) = parse_journal_lenient("\tA:B  $1\n2015-10-20 P\n  A:B  $1\n  C\n");
+	assert_eq!(entries.len(), 1);
+	assert_eq!(failures.len(), 1);
+	assert_eq!(failures[0].message, "Posting on line 1 has no transaction header");
+}
+
+
+
+/// Shifts the line numbers recorded in an entry parsed out of part of a file,
+/// so they refer to lines in the whole file.
+fn offset_line_numbers(entry: &mut Entry, offset: i32) {
+	if let Entry::Transaction(ref mut transaction) = *entry {
+		transaction.header.line_number += offset;
+		for posting in &mut transaction.postings {
+			posting.line_number += offset;
+		}
+	}
+}
+
+#[test]
+fn parse_journal_reader_multiple_transactions() {
+	let reader = io::Cursor::new("\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Cash\n\
+		\n\
+		\n\
+		2015-10-21 * Gas Station\n\
+		\tExpenses:Auto  $30.00\n\
+		\tAssets:Cash\n\
+		P 2015-10-25 AAPL $313.38\n\
+	");
+	let entries: Vec<Entry> = parse_journal_reader(reader)
+		.collect::<Result<_, _>>()
+		.unwrap();
+	assert_eq!(entries.len(), 3);
+	match entries[1] {
+		Entry::Transaction(ref transaction) => {
+			assert_eq!(transaction.header.payee, "Gas Station");
+			assert_eq!(transaction.header.line_number, 6);
+			assert_eq!(transaction.postings[1].line_number, 8);
+		},
+		_ => panic!("expected a transaction")
+	}
+	assert!(matches!(entries[2], Entry::Price(_)));
+}
+
+#[test]
+fn parse_journal_reader_year_across_blocks() {
+	let reader = io::Cursor::new("\
+		Y 2015\n\
+		\n\
+		10/17 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Cash\n\
+	");
+	let entries: Vec<Entry> = parse_journal_reader(reader)
+		.collect::<Result<_, _>>()
+		.unwrap();
+	assert!(matches!(entries[1], Entry::Transaction(ref t) if t.header.date.year == 2015));
+}
+
+#[test]
+fn parse_journal_reader_failure_line() {
+	let reader = io::Cursor::new("\
+		P 2015-10-25 AAPL $313.38\n\
+		\n\
+		P 2015-1x-25 AAPL $5.42\n\
+	");
+	let results: Vec<Result<Entry, ParseFailure>> = parse_journal_reader(reader).collect();
+	assert_eq!(results.len(), 2);
+	assert!(results[0].is_ok());
+	assert_eq!(results[1].as_ref().map_err(|failure| failure.line).err(), Some(3));
+}
+
+
+
+impl From<Vec<Entry>> for Ledger {
+	fn from(entries: Vec<Entry>) -> Ledger {
+		let mut transactions = Vec::new();
+		let mut prices = Vec::new();
+		let mut default_commodity = None;
+		let mut commodities = Vec::new();
+		let mut declared_accounts = Vec::new();
+		for entry in entries {
+			match entry {
+				Entry::Transaction(transaction) => transactions.push(transaction),
+				Entry::Price(price) => prices.push(price),
+				Entry::Include(_) => {},
+				Entry::DefaultCommodity(amount) => default_commodity = Some(amount),
+				Entry::Commodity(commodity) => commodities.push(commodity),
+				Entry::Account(account) => declared_accounts.push(account),
+				Entry::Year(_) | Entry::Comment(_) | Entry::ApplyTag(_) | Entry::EndApplyTag
+					| Entry::Periodic(_) => {}
+			}
+		}
+		Ledger {
+			transactions: transactions,
+			prices: PriceDb::new(prices),
+			default_commodity: default_commodity,
+			commodities: commodities,
+			declared_accounts: declared_accounts
+		}
+	}
+}
+
+impl Ledger {
+	/// The most recent price of a commodity in the base commodity, on or before
+	/// the given date.
+	fn price_in(&self, symbol: &Symbol, base: &Symbol, on: &Date) -> Option<&Amount> {
+		self.prices.history(symbol, None, Some(on.clone()))
+			.into_iter()
+			.rev()
+			.map(|price| &price.amount)
+			.find(|amount| amount.symbol == *base)
+	}
+
+	/// A copy of the ledger with posting amounts converted to the base
+	/// commodity, using the latest prices on or before the given date. Amounts
+	/// without a price are left unchanged; see `missing_prices`. Fails when a
+	/// converted amount is too large to hold.
+	pub fn reprice(&self, base: Symbol, on: Date) -> Result<Ledger, WealthError> {
+		let mut ledger = self.clone();
+		for posting in ledger.transactions.iter_mut().flat_map(|t| t.postings.iter_mut()) {
+			let priced = posting.amount.as_ref()
+				.filter(|amount| amount.symbol != base)
+				.and_then(|amount| self.price_in(&amount.symbol, &base, &on).map(|price| (amount, price)));
+			let (amount, price) = match priced {
+				Some(priced) => priced,
+				None => continue
+			};
+			let value = amount.value.checked_mul(price.value)
+				.ok_or(WealthError::Overflow(posting.line_number))?;
+			posting.amount = Some(Amount {
+				value: value,
+				symbol: price.symbol.clone(),
+				format: price.format
+			});
+		}
+		Ok(ledger)
+	}
+
+	/// Commodities used in posting amounts that have no price in the base
+	/// commodity on or before the given date, in order of first use.
+	pub fn missing_prices(&self, base: &Symbol, on: &Date) -> Vec<Symbol> {
+		let mut missing: Vec<Symbol> = Vec::new();
+		let amounts = self.transactions.iter()
+			.flat_map(|t| t.postings.iter())
+			.filter_map(|posting| posting.amount.as_ref());
+		for amount in amounts {
+			if amount.symbol != *base
+				&& self.price_in(&amount.symbol, base, on).is_none()
+				&& !missing.contains(&amount.symbol) {
+				missing.push(amount.symbol.clone());
+			}
+		}
+		missing
+	}
+}
+
+#[test]
+fn ledger_reprice_stock_holding() {
+	let ledger = Ledger::from(parse_journal("\
+		2015-10-20 * Broker\n\
+		\tAssets:Investments  10 AAPL\n\
+		\tAssets:Investments  5 \"MUTF2351\"\n\
+		\tAssets:Cash\n\
+		\n\
+		P 2015-10-23 AAPL $310.00\n\
+		P 2015-10-25 AAPL $313.38\n\
+		P 2015-10-30 AAPL $320.00\n\
+	").unwrap());
+	let dollars = Symbol {
+		value: "$".to_string(),
+		quoted: false
+	};
+	let on = Date {
+		year: 2015,
+		month: 10,
+		day: 26,
+		separator: DateSeparator::Hyphen
+	};
+	let repriced = ledger.reprice(dollars.clone(), on.clone()).unwrap();
+	let amounts: Vec<String> = repriced.transactions[0].postings.iter()
+		.map(|posting| posting.amount.as_ref().map_or(String::new(), |amount| amount.to_string()))
+		.collect();
+	assert_eq!(amounts, vec!["$3133.80".to_string(), "5 \"MUTF2351\"".to_string(), String::new()]);
+	assert_eq!(ledger.missing_prices(&dollars, &on), vec![Symbol {
+		value: "MUTF2351".to_string(),
+		quoted: true
+	}]);
+}
+
+#[test]
+fn ledger_reprice_overflow() {
+	let ledger = Ledger::from(parse_journal("\
+		2015-10-20 * Broker\n\
+		\tAssets:Investments  99999999999999999999 AAPL\n\
+		\tEquity:Opening\n\
+		\n\
+		P 2015-10-23 AAPL $99999999999999\n\
+	").unwrap());
+	let dollars = Symbol {
+		value: "$".to_string(),
+		quoted: false
+	};
+	let on = Date {
+		year: 2015,
+		month: 10,
+		day: 26,
+		separator: DateSeparator::Hyphen
+	};
+	assert_eq!(ledger.reprice(dollars, on).err(), Some(WealthError::Overflow(2)));
+}
+
+
+
+/// Format of the amount in a commodity directive's format line. e.g. $1,000.00
+fn directive_format(format: &str) -> Option<AmountFormat> {
+	try(parser(|input| amount_in(NumberFormat::DECIMAL_POINT, input)))
+		.or(parser(|input| amount_in(NumberFormat::DECIMAL_COMMA, input)))
+		.parse(format.trim())
+		.ok()
+		.map(|(amount, _)| amount.format)
+}
+
+impl Ledger {
+	/// The format each commodity should be displayed in: from its commodity
+	/// directive's format line or the D directive when there is one, otherwise
+	/// as it was first written in a transaction or price.
+	fn canonical_formats(&self) -> Vec<(Symbol, AmountFormat)> {
+		let mut formats: Vec<(Symbol, AmountFormat)> = Vec::new();
+		let mut learn = |symbol: &Symbol, format: AmountFormat| {
+			if !formats.iter().any(|known| known.0 == *symbol) {
+				formats.push((symbol.clone(), format));
+			}
+		};
+		for commodity in &self.commodities {
+			if let Some(format) = commodity.format.as_ref().and_then(|f| directive_format(f)) {
+				learn(&commodity.symbol, format);
+			}
+		}
+		if let Some(ref amount) = self.default_commodity {
+			learn(&amount.symbol, amount.format);
+		}
+		for posting in self.transactions.iter().flat_map(|t| t.postings.iter()) {
+			let amounts = [&posting.amount, &posting.lot_price, &posting.cost, &posting.balance_assertion];
+			for amount in amounts.iter().filter_map(|amount| amount.as_ref()) {
+				learn(&amount.symbol, amount.format);
+			}
+		}
+		for price in &self.prices.prices {
+			learn(&price.amount.symbol, price.amount.format);
+		}
+		formats
+	}
+
+	/// Rewrites every amount to its commodity's canonical format, so each
+	/// commodity is written the same way throughout.
+	pub fn normalize_amount_format(&mut self) {
+		let formats = self.canonical_formats();
+		let normalize = |amount: &mut Amount| {
+			if let Some(known) = formats.iter().find(|known| known.0 == amount.symbol) {
+				amount.format = known.1;
+			}
+		};
+		for posting in self.transactions.iter_mut().flat_map(|t| t.postings.iter_mut()) {
+			let mut amounts = [&mut posting.amount, &mut posting.lot_price, &mut posting.cost,
+				&mut posting.balance_assertion];
+			for amount in amounts.iter_mut().filter_map(|amount| amount.as_mut()) {
+				normalize(amount);
+			}
+		}
+		for price in self.prices.prices.iter_mut() {
+			normalize(&mut price.amount);
+		}
+	}
+}
+
+#[test]
+fn ledger_normalize_amount_format_first_use() {
+	let mut ledger = Ledger::from(parse_journal("\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $5\n\
+		\tAssets:Cash  -5 $\n\
+	").unwrap());
+	ledger.normalize_amount_format();
+	let amounts: Vec<String> = ledger.transactions[0].postings.iter()
+		.map(|posting| posting.amount.as_ref().unwrap().to_string())
+		.collect();
+	assert_eq!(amounts, vec!["$5".to_string(), "$-5".to_string()]);
+}
+
+#[test]
+fn ledger_normalize_amount_format_from_directive() {
+	let mut ledger = Ledger::from(parse_journal("\
+		commodity $\n\
+		\tformat 1,000.00 $\n\
+		\n\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $5\n\
+		\tAssets:Cash  -5 $\n\
+	").unwrap());
+	ledger.normalize_amount_format();
+	let amounts: Vec<String> = ledger.transactions[0].postings.iter()
+		.map(|posting| posting.amount.as_ref().unwrap().to_string())
+		.collect();
+	assert_eq!(amounts, vec!["5 $".to_string(), "-5 $".to_string()]);
+}
+
+
+
+impl Ledger {
+	/// The distinct payees of all transactions, trimmed of surrounding
+	/// whitespace.
+	pub fn payees(&self) -> BTreeSet<String> {
+		self.transactions.iter()
+			.map(|transaction| transaction.header.payee.trim().to_string())
+			.collect()
+	}
+}
+
+#[test]
+fn ledger_payees() {
+	let ledger = Ledger::from(parse_journal("\
+		2015-10-20 * Grocery Store ;weekly shop\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Cash\n\
+		\n\
+		2015-10-21 * Gas Station\n\
+		\tExpenses:Auto  $30.00\n\
+		\tAssets:Cash\n\
+		\n\
+		2015-10-27 * Grocery Store\n\
+		\tExpenses:Food  $52.10\n\
+		\tAssets:Cash\n\
+	").unwrap());
+	let payees: Vec<String> = ledger.payees().into_iter().collect();
+	assert_eq!(payees, vec!["Gas Station".to_string(), "Grocery Store".to_string()]);
+}
+
+
+
+impl Ledger {
+	/// Sorts transactions by date, keeping the file order of transactions on
+	/// the same day. With `effective` set, effective dates are used where
+	/// present.
+	pub fn sort_by_date(&mut self, effective: bool) {
+		self.transactions.sort_by(|a, b| a.sort_date(effective).cmp(b.sort_date(effective)));
+	}
+}
+
+#[test]
+fn ledger_sort_by_effective_date() {
+	let mut ledger = Ledger::from(parse_journal("\
+		2015-10-20=2015-10-25 * Paycheque\n\
+		\tAssets:Checking  $1000.00\n\
+		\tIncome:Salary\n\
+		\n\
+		2015-10-22 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Checking\n\
+	").unwrap());
+	let payees = |ledger: &Ledger| ledger.transactions.iter()
+		.map(|transaction| transaction.header.payee.clone())
+		.collect::<Vec<String>>();
+
+	ledger.sort_by_date(false);
+	assert_eq!(payees(&ledger), vec!["Paycheque".to_string(), "Grocery Store".to_string()]);
+	ledger.sort_by_date(true);
+	assert_eq!(payees(&ledger), vec!["Grocery Store".to_string(), "Paycheque".to_string()]);
+}
+
+
+
+impl Ledger {
+	/// Groups transactions by the top-level accounts of their postings, e.g.
+	/// Assets or Expenses. A transaction touching several roots appears under
+	/// each of them, once.
+	pub fn split_by_account_root(&self) -> BTreeMap<String, Vec<&Transaction>> {
+		let mut groups: BTreeMap<String, Vec<&Transaction>> = BTreeMap::new();
+		for transaction in &self.transactions {
+			let roots: BTreeSet<&String> = transaction.postings.iter()
+				.filter_map(|posting| posting.sub_accounts.first())
+				.collect();
+			for root in roots {
+				groups.entry(root.clone()).or_default().push(transaction);
+			}
+		}
+		groups
+	}
+}
+
+#[test]
+fn ledger_split_by_account_root() {
+	let ledger = Ledger::from(parse_journal("\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tExpenses:Household  $5.00\n\
+		\tAssets:Cash\n\
+		\n\
+		2015-10-21 * Paycheque\n\
+		\tAssets:Checking  $1000.00\n\
+		\tIncome:Salary\n\
+	").unwrap());
+	let groups = ledger.split_by_account_root();
+	let payees = |root: &str| groups[root].iter()
+		.map(|transaction| transaction.header.payee.clone())
+		.collect::<Vec<String>>();
+	assert_eq!(groups.keys().collect::<Vec<&String>>(), vec!["Assets", "Expenses", "Income"]);
+	assert_eq!(payees("Assets"), vec!["Grocery Store".to_string(), "Paycheque".to_string()]);
+	assert_eq!(payees("Expenses"), vec!["Grocery Store".to_string()]);
+	assert_eq!(payees("Income"), vec!["Paycheque".to_string()]);
+}
+
+
+
+/// How `Ledger::cashflow` groups postings into periods.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum CashflowPeriod {
+	/// Seven day periods, starting from the earliest income or expense.
+	Weekly,
+	/// Calendar months.
+	Monthly
+}
+
+/// Income and expenses over one period of a cashflow report, per commodity.
+#[derive(PartialEq, Debug, Clone)]
+pub struct CashflowRow {
+	/// The first day of the period.
+	pub start: Date,
+	/// Income received, as positive quantities.
+	pub inflows: BTreeMap<Symbol, Decimal>,
+	/// Expenses paid, as positive quantities.
+	pub outflows: BTreeMap<Symbol, Decimal>,
+	/// Inflows less outflows.
+	pub net: BTreeMap<Symbol, Decimal>
+}
+
+impl Ledger {
+	/// Sums the Income and Expenses postings in each period, from the period of
+	/// the earliest to that of the latest, counting postings by their own date
+	/// when they have one. Periods with neither are included, with empty sums.
+	pub fn cashflow(&self, period: CashflowPeriod) -> Vec<CashflowRow> {
+		let groups = self.split_by_account_root();
+		let mut flows: Vec<(Date, bool, Amount)> = Vec::new();
+		for root in &["Income", "Expenses"] {
+			for transaction in groups.get(*root).into_iter().flatten() {
+				let mut balanced = (*transaction).clone();
+				balanced.balance().ok();
+				for posting in balanced.postings {
+					if posting.sub_accounts.first().map(String::as_str) != Some(*root) {
+						continue;
+					}
+					if let Some(amount) = posting.amount {
+						let date = posting.date.unwrap_or(balanced.header.date.clone());
+						flows.push((date, *root == "Income", amount));
+					}
+				}
+			}
+		}
+
+		let dates = flows.iter().map(|flow| &flow.0);
+		let (first, last) = match (dates.clone().min(), dates.max()) {
+			(Some(first), Some(last)) => (first.clone(), last.clone()),
+			_ => return Vec::new()
+		};
+		let starts: Vec<Date> = match period {
+			CashflowPeriod::Weekly => Date::iter_days(&first, &last).step_by(7).collect(),
+			CashflowPeriod::Monthly => Date::iter_months(&first, &last).collect()
+		};
+		let mut rows: Vec<CashflowRow> = starts.into_iter()
+			.map(|start| CashflowRow {
+				start: start,
+				inflows: BTreeMap::new(),
+				outflows: BTreeMap::new(),
+				net: BTreeMap::new()
+			})
+			.collect();
+		for (date, inflow, amount) in flows {
+			if let Some(row) = rows.iter_mut().rev().find(|row| row.start <= date) {
+				// Income is posted as negative quantities, expenses as positive.
+				if inflow {
+					*row.inflows.entry(amount.symbol.clone()).or_default() -= amount.value;
+				} else {
+					*row.outflows.entry(amount.symbol.clone()).or_default() += amount.value;
+				}
+				*row.net.entry(amount.symbol).or_default() -= amount.value;
+			}
+		}
+		rows
+	}
+}
+
+#[test]
+fn ledger_cashflow_monthly() {
+	let ledger = Ledger::from(parse_journal("\
+		2015-10-01 * Paycheque\n\
+		\tAssets:Checking  $1000.00\n\
+		\tIncome:Salary\n\
+		\n\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Checking\n\
+		\n\
+		2015-11-02 * Landlord\n\
+		\tExpenses:Rent  $1200.00\n\
+		\tAssets:Checking\n\
+	").unwrap());
+	let sums = |sums: &BTreeMap<Symbol, Decimal>| sums.iter()
+		.map(|(symbol, value)| format!("{} {}", symbol.value, value))
+		.collect::<Vec<String>>();
+	let table: Vec<(String, Vec<String>, Vec<String>, Vec<String>)> = ledger.cashflow(CashflowPeriod::Monthly)
+		.iter()
+		.map(|row| (row.start.to_string(), sums(&row.inflows), sums(&row.outflows), sums(&row.net)))
+		.collect();
+	assert_eq!(table, vec![
+		("2015-10-01".to_string(), vec!["$ 1000.00".to_string()], vec!["$ 45.00".to_string()],
+			vec!["$ 955.00".to_string()]),
+		("2015-11-01".to_string(), vec![], vec!["$ 1200.00".to_string()],
+			vec!["$ -1200.00".to_string()])
+	]);
+}
+
+#[test]
+fn ledger_cashflow_weekly() {
+	let ledger = Ledger::from(parse_journal("\
+		2015-10-01 * Paycheque\n\
+		\tAssets:Checking  $1000.00\n\
+		\tIncome:Salary\n\
+		\n\
+		2015-10-15 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Checking\n\
+	").unwrap());
+	let rows = ledger.cashflow(CashflowPeriod::Weekly);
+	let starts: Vec<String> = rows.iter().map(|row| row.start.to_string()).collect();
+	assert_eq!(starts, vec!["2015-10-01".to_string(), "2015-10-08".to_string(), "2015-10-15".to_string()]);
+	assert!(rows[1].net.is_empty());
+	assert_eq!(rows[2].outflows.get(&Symbol { value: "$".to_string(), quoted: false }), Some(&Decimal::new(4500, 2)));
+}
+
+
+
+/// Options for `Ledger::register`.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct RegisterOptions {
+	/// Only postings to accounts whose names, joined with colons, start with
+	/// this. e.g. Expenses:Food
+	pub account_prefix: Option<String>,
+	/// Leave out virtual postings, as with ledger's --real.
+	pub real_only: bool,
+	/// Also keep a running average of the amounts, per commodity.
+	pub average: bool,
+	/// Sum each transaction's postings into one row per commodity, as with
+	/// ledger's --collapse.
+	pub collapse: bool
+}
+
+/// One posting in a register report.
+#[derive(PartialEq, Debug, Clone)]
+pub struct RegisterRow {
+	pub date: Date,
+	pub payee: String,
+	pub account: String,
+	pub amount: Amount,
+	/// The sum of the amounts so far in this row's commodity.
+	pub total: Amount,
+	/// The mean of the amounts so far in this row's commodity, when asked for.
+	pub average: Option<Amount>
+}
+
+impl Ledger {
+	/// Every posting with an amount to a matching account, in transaction
+	/// order, alongside running totals kept separately for each commodity. A
+	/// missing amount is inferred where the transaction balances. Fails when a
+	/// running total is too large to hold.
+	pub fn register(&self, options: &RegisterOptions) -> Result<Vec<RegisterRow>, WealthError> {
+		let mut running: BTreeMap<Symbol, (Decimal, u32)> = BTreeMap::new();
+		let mut rows = Vec::new();
+		for transaction in &self.transactions {
+			let mut balanced = transaction.clone();
+			balanced.balance().ok();
+			let mut postings: Vec<RegisterPosting> = Vec::new();
+			for posting in balanced.postings {
+				let matches = options.account_prefix.as_ref()
+					.is_none_or(|prefix| posting.sub_accounts.join(":").starts_with(prefix.as_str()))
+					&& !(options.real_only && posting.posting_type != PostingType::Real);
+				if let Some(amount) = posting.amount.filter(|_| matches) {
+					postings.push(RegisterPosting {
+						line_number: posting.line_number,
+						date: posting.date.unwrap_or(balanced.header.date.clone()),
+						account: posting.full_account,
+						amount: amount
+					});
+				}
+			}
+			if options.collapse {
+				postings = collapse_postings(&postings, &balanced.header.date)?;
+			}
+
+			for posting in postings {
+				let amount = posting.amount;
+				let &mut (ref mut total, ref mut count) = running.entry(amount.symbol.clone())
+					.or_insert((Decimal::ZERO, 0));
+				*total = total.checked_add(amount.value)
+					.ok_or(WealthError::Overflow(posting.line_number))?;
+				*count += 1;
+				let average = if options.average {
+					Some(Amount {
+						value: *total / Decimal::from(*count),
+						..amount.clone()
+					})
+				} else {
+					None
+				};
+				rows.push(RegisterRow {
+					date: posting.date,
+					payee: balanced.header.payee.clone(),
+					account: posting.account,
+					total: Amount {
+						value: *total,
+						..amount.clone()
+					},
+					average: average,
+					amount: amount
+				});
+			}
+		}
+		Ok(rows)
+	}
+}
+
+/// A posting picked out for a register row.
+struct RegisterPosting {
+	line_number: i32,
+	date: Date,
+	account: String,
+	amount: Amount
+}
+
+/// Sums a transaction's postings into one per commodity, in order of first
+/// use, dated with the transaction. A sum of several postings is labeled
+/// <Total> rather than with an account.
+fn collapse_postings(postings: &[RegisterPosting], date: &Date) -> Result<Vec<RegisterPosting>, WealthError> {
+	let amounts: Vec<Amount> = postings.iter().map(|posting| posting.amount.clone()).collect();
+	let groups = group_by_commodity(&amounts);
+	let mut collapsed: Vec<RegisterPosting> = Vec::new();
+	for posting in postings {
+		if collapsed.iter().any(|sum| sum.amount.symbol == posting.amount.symbol) {
+			continue;
+		}
+		let group = &groups[&posting.amount.symbol.value];
+		let mut value = Decimal::ZERO;
+		for amount in group {
+			value = value.checked_add(amount.value)
+				.ok_or(WealthError::Overflow(posting.line_number))?;
+		}
+		collapsed.push(RegisterPosting {
+			line_number: posting.line_number,
+			date: date.clone(),
+			account: if group.len() == 1 { posting.account.clone() } else { "<Total>".to_string() },
+			amount: Amount {
+				value: value,
+				..posting.amount.clone()
+			}
+		});
+	}
+	Ok(collapsed)
+}
+
+#[test]
+fn ledger_register_average() {
+	let ledger = Ledger::from(parse_journal("\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $10.00\n\
+		\tExpenses:Food  5 CAD\n\
+		\tAssets:Cash  -5 CAD\n\
+		\tAssets:Cash\n\
+		\n\
+		2015-10-21 * Grocery Store\n\
+		\tExpenses:Food  $20.00\n\
+		\tAssets:Cash  $-20.00\n\
+	").unwrap());
+	let options = RegisterOptions {
+		account_prefix: Some("Expenses".to_string()),
+		average: true,
+		..RegisterOptions::default()
+	};
+	let rows: Vec<(String, String, Option<Decimal>)> = ledger.register(&options).unwrap()
+		.iter()
+		.map(|row| (row.amount.to_string(), row.total.to_string(),
+			row.average.as_ref().map(|average| average.value)))
+		.collect();
+	assert_eq!(rows, vec![
+		("$10.00".to_string(), "$10.00".to_string(), Some(Decimal::new(10, 0))),
+		("5 CAD".to_string(), "5 CAD".to_string(), Some(Decimal::new(5, 0))),
+		("$20.00".to_string(), "$30.00".to_string(), Some(Decimal::new(15, 0)))
+	]);
+	assert!(ledger.register(&RegisterOptions::default()).unwrap()
+		.iter()
+		.all(|row| row.average.is_none()));
+}
+
+impl Ledger {
+	/// The total of each account's postings in each commodity, keyed by the
+	/// account's name joined with colons. A missing amount is inferred where
+	/// the transaction balances. With `real_only`, virtual postings are left
+	/// out, as with ledger's --real. Fails when a total is too large to hold.
+	pub fn balances(&self, real_only: bool) -> Result<BTreeMap<String, BTreeMap<Symbol, Decimal>>, WealthError> {
+		let mut balances: BTreeMap<String, BTreeMap<Symbol, Decimal>> = BTreeMap::new();
+		for transaction in &self.transactions {
+			let mut balanced = transaction.clone();
+			balanced.balance().ok();
+			for posting in balanced.postings {
+				if real_only && posting.posting_type != PostingType::Real {
+					continue;
+				}
+				if let Some(amount) = posting.amount {
+					let total = balances.entry(posting.sub_accounts.join(":"))
+						.or_default()
+						.entry(amount.symbol)
+						.or_default();
+					*total = total.checked_add(amount.value)
+						.ok_or(WealthError::Overflow(posting.line_number))?;
+				}
+			}
+		}
+		Ok(balances)
+	}
+}
+
+#[test]
+fn ledger_balances_real_only() {
+	let ledger = Ledger::from(parse_journal("\
+		2015-10-20 * Paycheque\n\
+		\tAssets:Checking  $1000.00\n\
+		\tIncome:Salary\n\
+		\t(Assets:Checking)  $-100.00\n\
+		\t[Budget:Food]  $100.00\n\
+		\t[Budget:Available]  $-100.00\n\
+	").unwrap());
+	let dollars = Symbol {
+		value: "$".to_string(),
+		quoted: false
+	};
+	let balances = ledger.balances(false).unwrap();
+	assert_eq!(balances["Assets:Checking"][&dollars], Decimal::new(90000, 2));
+	assert_eq!(balances["Budget:Food"][&dollars], Decimal::new(10000, 2));
+
+	let balances = ledger.balances(true).unwrap();
+	assert_eq!(balances["Assets:Checking"][&dollars], Decimal::new(100000, 2));
+	assert_eq!(balances.keys().collect::<Vec<&String>>(), vec!["Assets:Checking", "Income:Salary"]);
+
+	let options = RegisterOptions {
+		real_only: true,
+		..RegisterOptions::default()
+	};
+	assert_eq!(ledger.register(&options).unwrap().len(), 2);
+}
+
+#[test]
+fn ledger_register_collapse() {
+	let ledger = Ledger::from(parse_journal("\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tExpenses:Household  $5.00\n\
+		\tAssets:Cash\n\
+	").unwrap());
+	let options = RegisterOptions {
+		collapse: true,
+		..RegisterOptions::default()
+	};
+	let rows: Vec<(String, String, String)> = ledger.register(&options).unwrap()
+		.into_iter()
+		.map(|row| (row.payee, row.account, row.amount.to_string()))
+		.collect();
+	assert_eq!(rows, vec![("Grocery Store".to_string(), "<Total>".to_string(), "$0.00".to_string())]);
+
+	let options = RegisterOptions {
+		account_prefix: Some("Expenses".to_string()),
+		collapse: true,
+		..RegisterOptions::default()
+	};
+	let rows: Vec<(String, String, String)> = ledger.register(&options).unwrap()
+		.into_iter()
+		.map(|row| (row.payee, row.account, row.amount.to_string()))
+		.collect();
+	assert_eq!(rows, vec![("Grocery Store".to_string(), "<Total>".to_string(), "$50.00".to_string())]);
+}
+
+
+
+/// One account's budget over one period, against what was actually posted.
+#[derive(PartialEq, Debug, Clone)]
+pub struct BudgetRow {
+	/// The first day of the period.
+	pub start: Date,
+	pub account: String,
+	pub budgeted: Amount,
+	/// The sum of the postings in the budget's commodity to the account and
+	/// its sub-accounts, over the period.
+	pub actual: Amount,
+	/// Actual less budgeted: positive when over budget, negative when under.
+	pub difference: Amount
+}
+
+/// The start of the period after the one starting on `date`, or None when
+/// the period isn't understood.
+fn next_period(date: &Date, period: &Period) -> Option<Date> {
+	let (days, months) = match *period {
+		Period::Daily(interval) => (interval, 0),
+		Period::Weekly(interval) => (7 * interval, 0),
+		Period::Monthly(interval) => (0, interval),
+		Period::Yearly(interval) => (0, 12 * interval),
+		Period::Other(_) => return None
+	};
+	let mut next = date.clone();
+	for _ in 0..days {
+		next = next.next_day();
+	}
+	for _ in 0..months {
+		next = next.next_month();
+	}
+	Some(next)
+}
+
+/// Compares the postings of the periodic transactions among `entries`, taken
+/// as a budget, to the transactions among them, for each period from `from`
+/// to `to`. Monthly and yearly periods start on the first of the month, others
+/// on `from`. Periodic transactions with a period that isn't understood are
+/// skipped. Fails when a sum is too large to hold.
+pub fn budget_report(entries: &[Entry], from: &Date, to: &Date) -> Result<Vec<BudgetRow>, WealthError> {
+	let transactions: Vec<Transaction> = entries.iter()
+		.filter_map(|entry| match *entry {
+			Entry::Transaction(ref transaction) => {
+				let mut balanced = transaction.clone();
+				balanced.balance().ok();
+				Some(balanced)
+			},
+			_ => None
+		})
+		.collect();
+	let mut rows = Vec::new();
+	for entry in entries {
+		let periodic = match *entry {
+			Entry::Periodic(ref periodic) => periodic,
+			_ => continue
+		};
+		let mut start = match periodic.period {
+			Period::Monthly(_) | Period::Yearly(_) => Date { day: 1, ..from.clone() },
+			_ => from.clone()
+		};
+		while start <= *to {
+			let end = match next_period(&start, &periodic.period) {
+				Some(end) => end,
+				None => break
+			};
+			for budget in &periodic.postings {
+				let budgeted = match budget.amount {
+					Some(ref amount) => amount,
+					None => continue
+				};
+				let mut actual = Decimal::ZERO;
+				for transaction in &transactions {
+					for posting in &transaction.postings {
+						let date = posting.date.as_ref().unwrap_or(&transaction.header.date);
+						if *date < start || *date >= end || !posting.sub_accounts.starts_with(&budget.sub_accounts) {
+							continue;
+						}
+						if let Some(amount) = posting.amount.as_ref().filter(|amount| amount.symbol == budgeted.symbol) {
+							actual = actual.checked_add(amount.value)
+								.ok_or(WealthError::Overflow(posting.line_number))?;
+						}
+					}
+				}
+				let difference = actual.checked_sub(budgeted.value)
+					.ok_or(WealthError::Overflow(budget.line_number))?;
+				rows.push(BudgetRow {
+					start: start.clone(),
+					account: budget.full_account.clone(),
+					budgeted: budgeted.clone(),
+					actual: Amount {
+						value: actual,
+						..budgeted.clone()
+					},
+					difference: Amount {
+						value: difference,
+						..budgeted.clone()
+					}
+				});
+			}
+			start = end;
+		}
+	}
+	Ok(rows)
+}
+
+#[test]
+fn budget_report_one_month() {
+	let entries = parse_journal("\
+		~ monthly\n\
+		\tExpenses:Food  $400.00\n\
+		\tExpenses:Rent  $1200.00\n\
+		\tAssets:Checking\n\
+		\n\
+		2015-10-05 * Grocery Store\n\
+		\tExpenses:Food:Groceries  $250.00\n\
+		\tAssets:Checking\n\
+		\n\
+		2015-10-20 * Restaurant\n\
+		\tExpenses:Food:Dining  $200.00\n\
+		\tAssets:Checking\n\
+		\n\
+		2015-10-01 * Landlord\n\
+		\tExpenses:Rent  $1150.00\n\
+		\tAssets:Checking\n\
+		\n\
+		2015-11-02 * Grocery Store\n\
+		\tExpenses:Food:Groceries  $80.00\n\
+		\tAssets:Checking\n\
+	").unwrap();
+	let date = |day| Date {
+		year: 2015,
+		month: 10,
+		day: day,
+		separator: DateSeparator::Hyphen
+	};
+	let rows: Vec<(String, String, String, String, String)> = budget_report(&entries, &date(1), &date(31))
+		.unwrap()
+		.into_iter()
+		.map(|row| (row.start.to_string(), row.account, row.budgeted.to_string(),
+			row.actual.to_string(), row.difference.to_string()))
+		.collect();
+	assert_eq!(rows, vec![
+		("2015-10-01".to_string(), "Expenses:Food".to_string(), "$400.00".to_string(),
+			"$450.00".to_string(), "$50.00".to_string()),
+		("2015-10-01".to_string(), "Expenses:Rent".to_string(), "$1200.00".to_string(),
+			"$1150.00".to_string(), "$-50.00".to_string())
+	]);
+}
+
+
+
+/// A value in a register format expression.
+#[derive(PartialEq, Debug, Clone)]
+enum ExpressionValue {
+	Number(Decimal),
+	Amount(Amount),
+	Text(String)
+}
+
+impl fmt::Display for ExpressionValue {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ExpressionValue::Number(ref number) => write!(f, "{}", number),
+			ExpressionValue::Amount(ref amount) => write!(f, "{}", amount),
+			ExpressionValue::Text(ref text) => write!(f, "{}", text)
+		}
+	}
+}
+
+/// A lexical token of a register format expression.
+#[derive(PartialEq, Debug, Clone)]
+enum ExpressionToken {
+	Number(Decimal),
+	Name(String),
+	Operator(char),
+	Open,
+	Close
+}
+
+/// Renders a register row with a format template, in which each `%(...)` is
+/// replaced by the value of the expression inside. Expressions can use the
+/// row's date, payee, account, amount, total and average, numbers, + - * /
+/// and parentheses, e.g. `%(amount * 1.1)`. Scaled amounts keep their
+/// precision. An expression that can't be evaluated, such as one calling a
+/// function, is written out as is.
+pub fn format_register_row(template: &str, row: &RegisterRow) -> String {
+	let mut output = String::new();
+	let mut rest = template;
+	while let Some(start) = rest.find("%(") {
+		output.push_str(&rest[..start]);
+		let inside = &rest[start + 2..];
+		let end = match closing_paren(inside) {
+			Some(end) => end,
+			None => break
+		};
+		match evaluate_expression(&inside[..end], row) {
+			Some(value) => output.push_str(&value.to_string()),
+			None => output.push_str(&rest[start..start + 2 + end + 1])
+		}
+		rest = &inside[end + 1..];
+	}
+	output.push_str(rest);
+	output
+}
+
+/// The index of the `)` closing a parenthesis opened just before `text`.
+fn closing_paren(text: &str) -> Option<usize> {
+	let mut depth = 1;
+	for (index, c) in text.char_indices() {
+		match c {
+			'(' => depth += 1,
+			')' => {
+				depth -= 1;
+				if depth == 0 {
+					return Some(index);
+				}
+			},
+			_ => {}
+		}
+	}
+	None
+}
+
+/// Splits an expression into tokens. None when it holds a character that
+/// isn't part of any token.
+fn expression_tokens(text: &str) -> Option<Vec<ExpressionToken>> {
+	let mut tokens = Vec::new();
+	let mut chars = text.chars().peekable();
+	while let Some(&c) = chars.peek() {
+		if c.is_whitespace() {
+			chars.next();
+		} else if c.is_ascii_digit() || c == '.' {
+			let mut number = String::new();
+			while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+				number.push(c);
+				chars.next();
+			}
+			tokens.push(ExpressionToken::Number(Decimal::from_str(&number).ok()?));
+		} else if c.is_alphabetic() || c == '_' {
+			let mut name = String::new();
+			while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+				name.push(c);
+				chars.next();
+			}
+			tokens.push(ExpressionToken::Name(name));
+		} else {
+			tokens.push(match c {
+				'+' | '-' | '*' | '/' => ExpressionToken::Operator(c),
+				'(' => ExpressionToken::Open,
+				')' => ExpressionToken::Close,
+				_ => return None
+			});
+			chars.next();
+		}
+	}
+	Some(tokens)
+}
+
+/// Evaluates a register format expression against a row. None when it
+/// doesn't parse, names something unknown, or mixes values that can't be
+/// combined.
+fn evaluate_expression(text: &str, row: &RegisterRow) -> Option<ExpressionValue> {
+	let tokens = expression_tokens(text)?;
+	let mut position = 0;
+	let value = expression_sum(&tokens, &mut position, row)?;
+	if position == tokens.len() {
+		Some(value)
+	} else {
+		None
+	}
+}
+
+/// Terms joined by + and -.
+fn expression_sum(tokens: &[ExpressionToken], position: &mut usize, row: &RegisterRow) -> Option<ExpressionValue> {
+	let mut value = expression_product(tokens, position, row)?;
+	while let Some(&ExpressionToken::Operator(op)) = tokens.get(*position) {
+		if op != '+' && op != '-' {
+			break;
+		}
+		*position += 1;
+		let right = expression_product(tokens, position, row)?;
+		value = apply_operator(op, value, right)?;
+	}
+	Some(value)
+}
+
+/// Factors joined by * and /.
+fn expression_product(tokens: &[ExpressionToken], position: &mut usize, row: &RegisterRow) -> Option<ExpressionValue> {
+	let mut value = expression_factor(tokens, position, row)?;
+	while let Some(&ExpressionToken::Operator(op)) = tokens.get(*position) {
+		if op != '*' && op != '/' {
+			break;
+		}
+		*position += 1;
+		let right = expression_factor(tokens, position, row)?;
+		value = apply_operator(op, value, right)?;
+	}
+	Some(value)
+}
+
+/// A number, a row field, a negated factor or a parenthesized expression.
+fn expression_factor(tokens: &[ExpressionToken], position: &mut usize, row: &RegisterRow) -> Option<ExpressionValue> {
+	let token = tokens.get(*position)?.clone();
+	*position += 1;
+	match token {
+		ExpressionToken::Number(number) => Some(ExpressionValue::Number(number)),
+		ExpressionToken::Operator('-') => {
+			let value = expression_factor(tokens, position, row)?;
+			apply_operator('*', value, ExpressionValue::Number(Decimal::NEGATIVE_ONE))
+		},
+		ExpressionToken::Open => {
+			let value = expression_sum(tokens, position, row)?;
+			if tokens.get(*position) != Some(&ExpressionToken::Close) {
+				return None;
+			}
+			*position += 1;
+			Some(value)
+		},
+		// A name followed by a parenthesis is a function call, and there are
+		// no functions.
+		ExpressionToken::Name(_) if tokens.get(*position) == Some(&ExpressionToken::Open) => None,
+		ExpressionToken::Name(name) => match name.as_str() {
+			"date" => Some(ExpressionValue::Text(row.date.to_string())),
+			"payee" => Some(ExpressionValue::Text(row.payee.clone())),
+			"account" => Some(ExpressionValue::Text(row.account.clone())),
+			"amount" => Some(ExpressionValue::Amount(row.amount.clone())),
+			"total" => Some(ExpressionValue::Amount(row.total.clone())),
+			"average" => row.average.clone().map(ExpressionValue::Amount),
+			_ => None
+		},
+		ExpressionToken::Operator(_) | ExpressionToken::Close => None
+	}
+}
+
+/// Combines two values with an operator. Amounts can be scaled by numbers,
+/// keeping their precision, and added to or subtracted from amounts of the
+/// same commodity. None for any other combination, or on overflow.
+fn apply_operator(op: char, left: ExpressionValue, right: ExpressionValue) -> Option<ExpressionValue> {
+	let arithmetic = |left: Decimal, right: Decimal| match op {
+		'+' => left.checked_add(right),
+		'-' => left.checked_sub(right),
+		'*' => left.checked_mul(right),
+		'/' => left.checked_div(right),
+		_ => None
+	};
+	let scaled = |amount: Amount, value: Decimal| ExpressionValue::Amount(Amount {
+		value: value.round_dp(amount.precision()),
+		..amount
+	});
+	match (left, right) {
+		(ExpressionValue::Number(left), ExpressionValue::Number(right)) =>
+			arithmetic(left, right).map(ExpressionValue::Number),
+		(ExpressionValue::Amount(amount), ExpressionValue::Number(number)) if op == '*' || op == '/' =>
+			arithmetic(amount.value, number).map(|value| scaled(amount, value)),
+		(ExpressionValue::Number(number), ExpressionValue::Amount(amount)) if op == '*' =>
+			arithmetic(number, amount.value).map(|value| scaled(amount, value)),
+		(ExpressionValue::Amount(left), ExpressionValue::Amount(right))
+			if (op == '+' || op == '-') && left.symbol == right.symbol => {
+			let value = arithmetic(left.value, right.value)?;
+			Some(ExpressionValue::Amount(Amount {
+				value: value,
+				..left
+			}))
+		},
+		_ => None
+	}
+}
+
+#[test]
+fn format_register_row_scaled_amount() {
+	let ledger = Ledger::from(parse_journal("\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Cash\n\
+	").unwrap());
+	let rows = ledger.register(&RegisterOptions::default()).unwrap();
+	assert_eq!(format_register_row("%(date) %(payee)  %(account)  %(amount * 1.1)", &rows[0]),
+		"2015-10-20 Grocery Store  Expenses:Food  $49.50");
+	assert_eq!(format_register_row("%(-(amount + total) / 2)", &rows[0]), "$-45.00");
+	assert_eq!(format_register_row("%(round(amount)) %(amount * payee) 100%", &rows[0]),
+		"%(round(amount)) %(amount * payee) 100%");
+	assert_eq!(format_register_row("%(amount", &rows[0]), "%(amount");
+}
+
+
+
+impl Ledger {
+	/// Commodities that others are bought or priced in: the default commodity
+	/// and those of costs, lot prices and price directives.
+	fn cash_commodities(&self) -> BTreeSet<&Symbol> {
+		let mut cash: BTreeSet<&Symbol> = self.default_commodity.iter()
+			.map(|amount| &amount.symbol)
+			.collect();
+		for posting in self.transactions.iter().flat_map(|t| t.postings.iter()) {
+			let prices = [&posting.lot_price, &posting.cost];
+			cash.extend(prices.iter().filter_map(|price| price.as_ref()).map(|price| &price.symbol));
+		}
+		cash.extend(self.prices.prices.iter().map(|price| &price.amount.symbol));
+		cash
+	}
+
+	/// The quantity held of each commodity in Assets accounts at the end of
+	/// the given date, counting postings by their own date when they have one.
+	/// Cash, i.e. commodities that others are bought or priced in, is left
+	/// out, as are commodities that net to zero, e.g. shares since sold.
+	pub fn holdings_as_of(&self, on: Date) -> BTreeMap<Symbol, Decimal> {
+		let cash = self.cash_commodities();
+		let mut holdings: BTreeMap<Symbol, Decimal> = BTreeMap::new();
+		for transaction in &self.transactions {
+			for posting in &transaction.postings {
+				let date = posting.date.as_ref().unwrap_or(&transaction.header.date);
+				if *date > on || posting.sub_accounts.first().map(String::as_str) != Some("Assets") {
+					continue;
+				}
+				if let Some(ref amount) = posting.amount {
+					if !cash.contains(&amount.symbol) {
+						*holdings.entry(amount.symbol.clone()).or_default() += amount.value;
+					}
+				}
+			}
+		}
+		holdings.retain(|_, quantity| !quantity.is_zero());
+		holdings
+	}
+}
+
+#[test]
+fn ledger_holdings_as_of() {
+	let ledger = Ledger::from(parse_journal("\
+		2015-10-20 * Buy AAPL\n\
+		\tAssets:Broker  10 AAPL @ $110.00\n\
+		\tAssets:Cash  $-1100.00\n\
+		\n\
+		2015-10-23 * Buy AAPL\n\
+		\tAssets:Broker  5 AAPL @ $113.00\n\
+		\tAssets:Cash  $-565.00\n\
+		\n\
+		2015-11-02 * Buy MSFT\n\
+		\tAssets:Broker  4 MSFT @ $50.00\n\
+		\tAssets:Cash  $-200.00\n\
+	").unwrap());
+	let on = Date {
+		year: 2015,
+		month: 10,
+		day: 31,
+		separator: DateSeparator::Hyphen
+	};
+	let holdings: Vec<(String, Decimal)> = ledger.holdings_as_of(on)
+		.into_iter()
+		.map(|(symbol, quantity)| (symbol.value, quantity))
+		.collect();
+	assert_eq!(holdings, vec![("AAPL".to_string(), Decimal::new(15, 0))]);
+}
+
+
+
+/// An account in the tree of accounts used in a ledger.
+#[derive(PartialEq, Debug, Clone)]
+pub struct AccountNode {
+	/// The last part of the account name, e.g. Food for Expenses:Food.
+	pub name: String,
+	/// Sub-accounts, ordered by name.
+	pub children: Vec<AccountNode>,
+	/// Whether any posting is to this account itself, rather than only to its
+	/// sub-accounts.
+	pub has_transactions: bool
+}
+
+impl AccountNode {
+	/// Adds the account with the given sub-accounts among `nodes`, along with
+	/// any parents it's missing. `posted` is whether any posting is to it.
+	fn insert(nodes: &mut Vec<AccountNode>, sub_accounts: &[String], posted: bool) {
+		let (name, rest) = match sub_accounts.split_first() {
+			Some(parts) => parts,
+			None => return
+		};
+		let index = match nodes.iter().position(|node| node.name == *name) {
+			Some(index) => index,
+			None => {
+				nodes.push(AccountNode {
+					name: name.clone(),
+					children: Vec::new(),
+					has_transactions: false
+				});
+				nodes.len() - 1
+			}
+		};
+		if rest.is_empty() {
+			nodes[index].has_transactions |= posted;
+		} else {
+			AccountNode::insert(&mut nodes[index].children, rest, posted);
+		}
+	}
+}
+
+impl Ledger {
+	/// The accounts posted to in the ledger's transactions.
+	fn posted_accounts(&self) -> BTreeSet<&[String]> {
+		self.transactions.iter()
+			.flat_map(|transaction| transaction.postings.iter())
+			.map(|posting| &posting.sub_accounts[..])
+			.collect()
+	}
+
+	/// The accounts declared with account directives or posted to in the
+	/// ledger's transactions, ordered by name.
+	pub fn accounts(&self) -> BTreeSet<&[String]> {
+		let mut accounts = self.posted_accounts();
+		accounts.extend(self.declared_accounts.iter().map(|account| &account.sub_accounts[..]));
+		accounts
+	}
+
+	/// The declared and posted to accounts whose names, joined with colons,
+	/// start with `prefix`, sorted. Meant for completing account names in an
+	/// editor.
+	pub fn accounts_matching(&self, prefix: &str, case_insensitive: bool) -> Vec<String> {
+		let prefix = if case_insensitive { prefix.to_lowercase() } else { prefix.to_string() };
+		let mut matches: Vec<String> = self.accounts().into_iter()
+			.map(|sub_accounts| sub_accounts.join(":"))
+			.filter(|account| if case_insensitive {
+				account.to_lowercase().starts_with(&prefix)
+			} else {
+				account.starts_with(&prefix)
+			})
+			.collect();
+		matches.sort();
+		matches.dedup();
+		matches
+	}
+
+	/// The declared and posted to accounts as a hierarchy, e.g. Expenses with
+	/// children Food and Transport. Accounts that are never posted to
+	/// themselves, such as parents or declared but unused accounts, are
+	/// included with `has_transactions` false.
+	pub fn account_tree(&self) -> Vec<AccountNode> {
+		let posted = self.posted_accounts();
+		let mut roots = Vec::new();
+		for sub_accounts in self.accounts() {
+			AccountNode::insert(&mut roots, sub_accounts, posted.contains(sub_accounts));
+		}
+		roots
+	}
+}
+
+#[test]
+fn ledger_account_tree() {
+	let ledger = Ledger::from(parse_journal("\
+		account Expenses:Food:Dining\n\
+		\n\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food:Groceries  $45.00\n\
+		\tExpenses:Transport  $5.00\n\
+		\tExpenses\n\
+	").unwrap());
+	let node = |name: &str, children: Vec<AccountNode>, has_transactions: bool| AccountNode {
+		name: name.to_string(),
+		children: children,
+		has_transactions: has_transactions
+	};
+	assert_eq!(ledger.account_tree(), vec![
+		node("Expenses", vec![
+			node("Food", vec![
+				node("Dining", vec![], false),
+				node("Groceries", vec![], true)
+			], false),
+			node("Transport", vec![], true)
+		], true)
+	]);
+}
+
+#[test]
+fn ledger_accounts_matching() {
+	let ledger = Ledger::from(parse_journal("\
+		account Expenses:Food:Dining\n\
+		\n\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food:Groceries  $45.00\n\
+		\texpenses:Misc  $5.00\n\
+		\tAssets:Checking\n\
+	").unwrap());
+	assert_eq!(ledger.accounts_matching("Exp", false), vec![
+		"Expenses:Food:Dining".to_string(),
+		"Expenses:Food:Groceries".to_string()
+	]);
+	assert_eq!(ledger.accounts_matching("Exp", true), vec![
+		"Expenses:Food:Dining".to_string(),
+		"Expenses:Food:Groceries".to_string(),
+		"expenses:Misc".to_string()
+	]);
+	assert!(ledger.accounts_matching("Income", true).is_empty());
+}
+
+
+
+/// Checks run by `Ledger::validate` beyond balancing and balance assertions.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct ValidateOptions {
+	/// Also report accounts and commodities used without an account or
+	/// commodity directive.
+	pub strict: bool
+}
+
+impl Ledger {
+	/// Runs every check on the ledger and returns all the problems found, in
+	/// transaction order: transactions that don't balance, balance assertions
+	/// that don't match the account's running balance and, when strict,
+	/// undeclared accounts and commodities.
+	pub fn validate(&self, options: ValidateOptions) -> Vec<WealthError> {
+		let declared_accounts: BTreeSet<&[String]> = self.declared_accounts.iter()
+			.map(|account| &account.sub_accounts[..])
+			.collect();
+		let declared_commodities: BTreeSet<&Symbol> = self.commodities.iter()
+			.map(|commodity| &commodity.symbol)
+			.collect();
+		let mut running: BTreeMap<(Vec<String>, Symbol), Decimal> = BTreeMap::new();
+		let mut errors = Vec::new();
+		for transaction in &self.transactions {
+			if let Err(error) = transaction.validate_balance() {
+				errors.push(error);
+			}
+			// An unbalanced transaction is reported above; its known amounts
+			// still count towards the running balances.
+			let mut balanced = transaction.clone();
+			balanced.balance().ok();
+			for posting in &balanced.postings {
+				if let Some(ref amount) = posting.amount {
+					*running.entry((posting.sub_accounts.clone(), amount.symbol.clone()))
+						.or_default() += amount.value;
+				}
+				if let Some(ref assertion) = posting.balance_assertion {
+					let key = (posting.sub_accounts.clone(), assertion.symbol.clone());
+					if running.get(&key).cloned().unwrap_or_default() != assertion.value {
+						errors.push(WealthError::FailedAssertion(posting.line_number));
+					}
+				}
+			}
+			if !options.strict {
+				continue;
+			}
+			for posting in &transaction.postings {
+				if !declared_accounts.contains(&posting.sub_accounts[..]) {
+					errors.push(WealthError::UndeclaredAccount(posting.line_number,
+						posting.full_account.clone()));
+				}
+				let amounts = [&posting.amount, &posting.lot_price, &posting.cost, &posting.balance_assertion];
+				for amount in amounts.iter().filter_map(|amount| amount.as_ref()) {
+					if !declared_commodities.contains(&amount.symbol) {
+						errors.push(WealthError::UndeclaredCommodity(posting.line_number,
+							amount.symbol.value.clone()));
+					}
+				}
+			}
+		}
+		errors
+	}
+}
+
+#[test]
+fn ledger_validate() {
+	let ledger = Ledger::from(parse_journal("\
+		account Assets:Checking\n\
+		account Income:Salary\n\
+		\n\
+		commodity $\n\
+		\n\
+		2015-10-20 * Paycheque\n\
+		\tAssets:Checking  $1000.00\n\
+		\tIncome:Salary\n\
+		\n\
+		2015-10-21 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Checking  $-40.00 = $950.00\n\
+		\n\
+		2015-10-22 * Bookstore\n\
+		\tExpenses:Books  10 CAD\n\
+		\tAssets:Checking  $-10.00\n\
+	").unwrap());
+	assert_eq!(ledger.validate(ValidateOptions::default()), vec![
+		WealthError::Unbalanced(10),
+		WealthError::FailedAssertion(12),
+		WealthError::Unbalanced(14)
+	]);
+	assert_eq!(ledger.validate(ValidateOptions { strict: true }), vec![
+		WealthError::Unbalanced(10),
+		WealthError::FailedAssertion(12),
+		WealthError::UndeclaredAccount(11, "Expenses:Food".to_string()),
+		WealthError::Unbalanced(14),
+		WealthError::UndeclaredAccount(15, "Expenses:Books".to_string()),
+		WealthError::UndeclaredCommodity(15, "CAD".to_string())
+	]);
+}
+
+
+
+/// Writes the postings of the transactions among `entries` as CSV, one row per
+/// posting under a header row. Amount columns are empty for postings without
+/// an amount.
+pub fn to_csv(entries: &[Entry]) -> String {
+	let mut csv = String::from("date,status,payee,account,amount,symbol\n");
+	for entry in entries {
+		if let Entry::Transaction(ref transaction) = *entry {
+			let header = &transaction.header;
+			let status = match header.status {
+				TransactionStatus::Cleared => "*",
+				TransactionStatus::Uncleared => "!",
+				TransactionStatus::Unmarked => ""
+			};
+			for posting in &transaction.postings {
+				let (value, symbol) = match posting.amount {
+					Some(ref amount) => (amount.value.to_string(), amount.symbol.value.clone()),
+					None => (String::new(), String::new())
+				};
+				let fields = [
+					header.date.to_string(),
+					status.to_string(),
+					header.payee.trim().to_string(),
+					posting.full_account.clone(),
+					value,
+					symbol
+				];
+				let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+				csv.push_str(&row.join(","));
+				csv.push('\n');
+			}
+		}
+	}
+	csv
+}
+
+/// Quotes a CSV field if it holds a comma, quote or line break, doubling any
+/// quotes inside it.
+fn csv_field(field: &str) -> String {
+	if field.contains(&[',', '"', '\n', '\r'][..]) {
+		format!("\"{}\"", field.replace('"', "\"\""))
+	} else {
+		field.to_string()
+	}
+}
+
+#[test]
+fn to_csv_test() {
+	let entries = parse_journal("\
+		2015-10-20 * WonderMart - groceries, light bulbs\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Cash\n\
+		\n\
+		P 2015-10-23 AAPL $313.38\n\
+	").unwrap();
+	let csv = to_csv(&entries);
+	let rows: Vec<&str> = csv.lines().collect();
+	assert_eq!(rows, vec![
+		"date,status,payee,account,amount,symbol",
+		"2015-10-20,*,\"WonderMart - groceries, light bulbs\",Expenses:Food,45.00,$",
+		"2015-10-20,*,\"WonderMart - groceries, light bulbs\",Assets:Cash,,"
+	]);
+}
+
+#[test]
+fn csv_field_doubles_quotes() {
+	assert_eq!(csv_field("10 \"AAPL\""), "\"10 \"\"AAPL\"\"\"");
+	assert_eq!(csv_field("Assets:Cash"), "Assets:Cash");
+}
+
+
+
+impl fmt::Display for Entry {
+	/// Writes the entry back out as ledger text. Comments are written with a
+	/// `;`, whichever character they were parsed with.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Entry::Transaction(ref transaction) => write!(f, "{}", transaction),
+			Entry::Price(ref price) => write!(f, "{}", price),
+			Entry::Include(ref path) => write!(f, "include {}", path),
+			Entry::DefaultCommodity(ref amount) => write!(f, "D {}", amount),
+			Entry::Commodity(ref commodity) => {
+				write!(f, "commodity {}", commodity.symbol)?;
+				if let Some(ref format) = commodity.format {
+					write!(f, "\n    format {}", format)?;
+				}
+				Ok(())
+			},
+			Entry::Account(ref account) => {
+				write!(f, "account {}", account.full_account)?;
+				if let Some(ref note) = account.note {
+					write!(f, "\n    note {}", note)?;
+				}
+				for alias in &account.aliases {
+					write!(f, "\n    alias {}", alias)?;
+				}
+				Ok(())
+			},
+			Entry::Year(year) => write!(f, "Y {}", year),
+			Entry::Comment(ref text) => write!(f, ";{}", text),
+			Entry::ApplyTag(ref tag) => write!(f, "apply tag {}", tag),
+			Entry::EndApplyTag => write!(f, "end apply tag"),
+			Entry::Periodic(ref periodic) => {
+				write!(f, "~ {}", periodic.period)?;
+				for posting in &periodic.postings {
+					write!(f, "\n{}", posting)?;
+				}
+				Ok(())
+			}
+		}
+	}
+}
+
+/// Pretty-prints journal entries, one per line with a blank line either side
+/// of each transaction.
+pub fn format_journal(entries: &[Entry]) -> String {
+	let mut text = String::new();
+	let mut previous: Option<&Entry> = None;
+	for entry in entries {
+		let is_transaction = |entry: &Entry| matches!(*entry, Entry::Transaction(_) | Entry::Periodic(_));
+		if let Some(previous) = previous {
+			if is_transaction(previous) || is_transaction(entry) {
+				text.push('\n');
+			}
+		}
+		text.push_str(&entry.to_string());
+		text.push('\n');
+		previous = Some(entry);
+	}
+	text
+}
+
+/// Checks `format_journal` against the golden files in tests/format: each
+/// .ledger fixture formats to the contents of its .expected file. Run with
+/// UPDATE_EXPECTED=1 to rewrite the .expected files from the current output.
+#[test]
+fn format_journal_golden_files() {
+	let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("format");
+	let update = std::env::var_os("UPDATE_EXPECTED").is_some();
+	let mut fixtures: Vec<_> = std::fs::read_dir(&directory).unwrap()
+		.map(|entry| entry.unwrap().path())
+		.filter(|path| path.extension().is_some_and(|extension| extension == "ledger"))
+		.collect();
+	fixtures.sort();
+	assert!(fixtures.len() >= 2, "no fixtures found in {}", directory.display());
+
+	for fixture in fixtures {
+		let input = std::fs::read_to_string(&fixture).unwrap();
+		let output = format_journal(&parse_journal(&input).unwrap());
+		let expected_path = fixture.with_extension("expected");
+		if update {
+			std::fs::write(&expected_path, &output).unwrap();
+		} else {
+			let expected = std::fs::read_to_string(&expected_path).unwrap();
+			assert_eq!(output, expected, "{} doesn't match {}", fixture.display(), expected_path.display());
+		}
+	}
+}
+
+
+
+/// Active ISO 4217 currency codes, sorted so they can be binary searched.
+#[cfg(feature = "iso4217")]
+const ISO_4217_CODES: [&str; 180] = [
+	"AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD",
+	"BDT", "BGN", "BHD", "BIF", "BMD", "BND", "BOB", "BOV", "BRL", "BSD", "BTN", "BWP",
+	"BYN", "BZD", "CAD", "CDF", "CHE", "CHF", "CHW", "CLF", "CLP", "CNY", "COP", "COU",
+	"CRC", "CUC", "CUP", "CVE", "CZK", "DJF", "DKK", "DOP", "DZD", "EGP", "ERN", "ETB",
+	"EUR", "FJD", "FKP", "GBP", "GEL", "GHS", "GIP", "GMD", "GNF", "GTQ", "GYD", "HKD",
+	"HNL", "HTG", "HUF", "IDR", "ILS", "INR", "IQD", "IRR", "ISK", "JMD", "JOD", "JPY",
+	"KES", "KGS", "KHR", "KMF", "KPW", "KRW", "KWD", "KYD", "KZT", "LAK", "LBP", "LKR",
+	"LRD", "LSL", "LYD", "MAD", "MDL", "MGA", "MKD", "MMK", "MNT", "MOP", "MRU", "MUR",
+	"MVR", "MWK", "MXN", "MXV", "MYR", "MZN", "NAD", "NGN", "NIO", "NOK", "NPR", "NZD",
+	"OMR", "PAB", "PEN", "PGK", "PHP", "PKR", "PLN", "PYG", "QAR", "RON", "RSD", "RUB",
+	"RWF", "SAR", "SBD", "SCR", "SDG", "SEK", "SGD", "SHP", "SLE", "SLL", "SOS", "SRD",
+	"SSP", "STN", "SVC", "SYP", "SZL", "THB", "TJS", "TMT", "TND", "TOP", "TRY", "TTD",
+	"TWD", "TZS", "UAH", "UGX", "USD", "USN", "UYI", "UYU", "UYW", "UZS", "VED", "VES",
+	"VND", "VUV", "WST", "XAF", "XAG", "XAU", "XBA", "XBB", "XBC", "XBD", "XCD", "XDR",
+	"XOF", "XPD", "XPF", "XPT", "XSU", "XTS", "XUA", "XXX", "YER", "ZAR", "ZMW", "ZWL"
+];
+
+/// What kind of commodity a symbol names, as far as ISO 4217 can tell.
+#[cfg(feature = "iso4217")]
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SymbolClass {
+	/// A known ISO 4217 currency code, e.g. USD.
+	Currency,
+	/// Three capital letters that aren't a known ISO 4217 code, e.g. XYZ.
+	UnknownCode,
+	/// Anything else, e.g. $ or AAPL.
+	Other
+}
+
+/// A symbol that looks like an ISO 4217 currency code but isn't one, found on
+/// the given line.
+#[cfg(feature = "iso4217")]
+#[derive(PartialEq, Debug, Clone)]
+pub struct CurrencyWarning {
+	pub line: i32,
+	pub symbol: Symbol
+}
+
+#[cfg(feature = "iso4217")]
+impl fmt::Display for CurrencyWarning {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Unknown ISO 4217 currency code {} on line {}", self.symbol, self.line)
+	}
+}
+
+#[cfg(feature = "iso4217")]
+impl Symbol {
+	/// Classifies the symbol against the ISO 4217 currency codes.
+	pub fn classify(&self) -> SymbolClass {
+		let looks_like_code = self.value.len() == 3
+			&& self.value.chars().all(|c| c.is_ascii_uppercase());
+		if !looks_like_code {
+			SymbolClass::Other
+		} else if ISO_4217_CODES.binary_search(&self.value.as_str()).is_ok() {
+			SymbolClass::Currency
+		} else {
+			SymbolClass::UnknownCode
+		}
+	}
+}
+
+#[cfg(feature = "iso4217")]
+impl Ledger {
+	/// Warnings for posting amounts, lot prices and costs whose symbol looks
+	/// like an ISO 4217 currency code but isn't one. These aren't errors, as a
+	/// three letter symbol may just as well be a ticker.
+	pub fn currency_warnings(&self) -> Vec<CurrencyWarning> {
+		let mut warnings = Vec::new();
+		for transaction in &self.transactions {
+			for posting in &transaction.postings {
+				let amounts = [&posting.amount, &posting.lot_price, &posting.cost];
+				for amount in amounts.iter().filter_map(|amount| amount.as_ref()) {
+					if amount.symbol.classify() == SymbolClass::UnknownCode {
+						warnings.push(CurrencyWarning {
+							line: posting.line_number,
+							symbol: amount.symbol.clone()
+						});
+					}
+				}
+			}
+		}
+		warnings
+	}
+}
+
+#[cfg(feature = "iso4217")]
+#[test]
+fn symbol_classify() {
+	let symbol = |value: &str| Symbol {
+		value: value.to_string(),
+		quoted: false
+	};
+	assert_eq!(symbol("USD").classify(), SymbolClass::Currency);
+	assert_eq!(symbol("XYZ").classify(), SymbolClass::UnknownCode);
+	assert_eq!(symbol("$").classify(), SymbolClass::Other);
+	assert_eq!(symbol("AAPL").classify(), SymbolClass::Other);
+}
+
+#[cfg(feature = "iso4217")]
+#[test]
+fn ledger_currency_warnings() {
+	let ledger = Ledger::from(parse_journal("\
+		2015-10-20 * Exchange\n\
+		\tAssets:Cash  100 USD\n\
+		\tAssets:Travel  -100 XYZ\n\
+	").unwrap());
+	let warnings = ledger.currency_warnings();
+	assert_eq!(warnings, vec![CurrencyWarning {
+		line: 3,
+		symbol: Symbol {
+			value: "XYZ".to_string(),
+			quoted: false
+		}
+	}]);
+	assert_eq!(warnings[0].to_string(), "Unknown ISO 4217 currency code XYZ on line 3");
+}
+
+
+
+/// A conflict between two ledgers being merged. The first ledger's directive
+/// is kept.
+#[derive(PartialEq, Debug, Clone)]
+pub enum MergeWarning {
+	/// The ledgers set different default commodities.
+	DefaultCommodity {
+		kept: Amount,
+		dropped: Amount
+	},
+	/// The ledgers declare the same commodity differently.
+	Commodity {
+		kept: CommodityDirective,
+		dropped: CommodityDirective
+	}
+}
+
+impl fmt::Display for MergeWarning {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			MergeWarning::DefaultCommodity { ref kept, ref dropped } =>
+				write!(f, "Default commodity {} conflicts with {}, keeping {}", dropped, kept, kept),
+			MergeWarning::Commodity { ref kept, .. } =>
+				write!(f, "Commodity {} is declared differently, keeping the first", kept.symbol)
+		}
+	}
+}
+
+impl Ledger {
+	/// Combines two ledgers, e.g. parsed from separate files. Transactions and
+	/// prices are concatenated and sorted by date, keeping file order within a
+	/// day. With `dedup` set, transactions that print identically to one
+	/// already kept are dropped. Conflicting directives keep this ledger's and
+	/// are reported as warnings.
+	pub fn merge(self, other: Ledger, dedup: bool) -> (Ledger, Vec<MergeWarning>) {
+		let mut warnings = Vec::new();
+
+		let mut transactions = self.transactions;
+		transactions.extend(other.transactions);
+		if dedup {
+			let mut seen = BTreeSet::new();
+			transactions.retain(|transaction| seen.insert(transaction.to_string()));
+		}
+
+		let mut prices = self.prices.prices;
+		prices.extend(other.prices.prices);
+		prices.sort_by(|a, b| a.date.cmp(&b.date));
+
+		let default_commodity = match (self.default_commodity, other.default_commodity) {
+			(Some(kept), Some(dropped)) => {
+				if kept != dropped {
+					warnings.push(MergeWarning::DefaultCommodity {
+						kept: kept.clone(),
+						dropped: dropped
+					});
+				}
+				Some(kept)
+			},
+			(kept, dropped) => kept.or(dropped)
+		};
+
+		let mut commodities = self.commodities;
+		for commodity in other.commodities {
+			let existing = commodities.iter().find(|kept| kept.symbol == commodity.symbol).cloned();
+			match existing {
+				Some(ref kept) if *kept == commodity => {},
+				Some(kept) => warnings.push(MergeWarning::Commodity {
+					kept: kept,
+					dropped: commodity
+				}),
+				None => commodities.push(commodity)
+			}
+		}
+
+		let mut declared_accounts = self.declared_accounts;
+		for account in other.declared_accounts {
+			if !declared_accounts.iter().any(|kept| kept.full_account == account.full_account) {
+				declared_accounts.push(account);
+			}
+		}
+
+		let mut ledger = Ledger {
+			transactions: transactions,
+			prices: PriceDb::new(prices),
+			default_commodity: default_commodity,
+			commodities: commodities,
+			declared_accounts: declared_accounts
+		};
+		ledger.sort_by_date(false);
+		(ledger, warnings)
+	}
+}
+
+#[test]
+fn ledger_merge() {
+	let first = Ledger::from(parse_journal("\
+		D $1,000.00\n\
+		\n\
+		2015-10-22 * Gas Station\n\
+		\tExpenses:Auto  $30.00\n\
+		\tAssets:Cash\n\
+		\n\
+		P 2015-10-23 AAPL $313.38\n\
+	").unwrap());
+	let second = Ledger::from(parse_journal("\
+		D 1.000,00 EUR\n\
+		\n\
+		2015-10-20 * Grocery Store\n\
+		\tExpenses:Food  $45.00\n\
+		\tAssets:Cash\n\
+		\n\
+		2015-10-22 * Gas Station\n\
+		\tExpenses:Auto  $30.00\n\
+		\tAssets:Cash\n\
+		\n\
+		P 2015-10-21 AAPL $310.00\n\
+	").unwrap());
+
+	let (merged, warnings) = first.clone().merge(second.clone(), false);
+	assert_eq!(merged.transactions.len(), 3);
+
+	let (merged, warnings_deduped) = first.merge(second, true);
+	let payees: Vec<&str> = merged.transactions.iter()
+		.map(|transaction| transaction.header.payee.as_str())
+		.collect();
+	assert_eq!(payees, vec!["Grocery Store", "Gas Station"]);
+	let price_days: Vec<i32> = merged.prices.prices.iter().map(|price| price.date.day).collect();
+	assert_eq!(price_days, vec![21, 23]);
+	assert_eq!(merged.default_commodity.map(|amount| amount.symbol.value), Some("$".to_string()));
+	assert_eq!(warnings.len(), 1);
+	assert_eq!(warnings, warnings_deduped);
+	assert!(matches!(warnings[0], MergeWarning::DefaultCommodity { .. }));
+}